@@ -1,6 +1,13 @@
 use codespan::{ByteSpan, ByteIndex};
+use num_bigint::BigInt;
+use serde::{Serialize, Deserialize};
 use std::{
 	convert::TryInto,
+	fmt,
+};
+use crate::{
+	typeck::Type,
+	util::{escape, join, mapping},
 };
 use derive_more::{
 	Display,
@@ -8,7 +15,7 @@ use derive_more::{
 	Into,
 };
 
-#[derive(Debug, Display, Clone, From)]
+#[derive(Debug, Display, Clone, PartialEq, From)]
 pub struct Span(ByteSpan);
 
 impl Span {
@@ -19,7 +26,8 @@ impl Span {
 	}
 }
 
-#[derive(Debug, Display, From, Into, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Display, From, Into, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Name(String);
 
 impl AsRef<str> for Name {
@@ -34,9 +42,6 @@ impl<'a> From<&'a str> for Name {
 	}
 }
 
-#[derive(Debug, Display, From, Clone, Copy, Hash, Eq, PartialEq)]
-pub struct Number(i64);
-
 #[derive(Debug, Clone)]
 pub struct Expr {
 	pub kind: ExprKind,
@@ -47,23 +52,254 @@ impl Expr {
 	pub fn new((kind, span): (ExprKind, Span)) -> Self {
 		Self {kind, span}
 	}
+
+	/// Returns a copy of this expression with `f` applied to each of the
+	/// expressions directly inside it, including the ones in statements
+	pub fn map(&self, mut f: impl FnMut(&Expr) -> Expr) -> Expr {
+		let mut map_pairs = |pairs: &[(Ident, Expr)]| {
+			pairs.iter().map(|(ident, expr)| (ident.clone(), f(expr))).collect()
+		};
+
+		let kind = match &self.kind {
+			ExprKind::RecordValue(pairs) => ExprKind::RecordValue(map_pairs(pairs)),
+			ExprKind::RecordType(pairs) => ExprKind::RecordType(map_pairs(pairs)),
+			ExprKind::RecordExtension(record, pairs) => {
+				let pairs = map_pairs(pairs);
+				ExprKind::RecordExtension(Box::new(f(record)), pairs)
+			}
+			ExprKind::RecordRemoval(record, idents) => {
+				ExprKind::RecordRemoval(Box::new(f(record)), idents.clone())
+			}
+			ExprKind::Tuple(exprs) => ExprKind::Tuple(exprs.iter().map(f).collect()),
+			ExprKind::TupleType(elements) => {
+				ExprKind::TupleType(elements.iter().map(|(ident, expr)| (ident.clone(), f(expr))).collect())
+			}
+			ExprKind::List(exprs) => ExprKind::List(exprs.iter().map(f).collect()),
+			ExprKind::ListType(expr) => ExprKind::ListType(Box::new(f(expr))),
+			ExprKind::Map(entries) => {
+				ExprKind::Map(entries.iter().map(|(key, value)| (f(key), f(value))).collect())
+			}
+			ExprKind::MapType(key, value) => ExprKind::MapType(Box::new(f(key)), Box::new(f(value))),
+			ExprKind::Set(exprs) => ExprKind::Set(exprs.iter().map(f).collect()),
+			ExprKind::SetType(expr) => ExprKind::SetType(Box::new(f(expr))),
+			ExprKind::Block(stmts, expr) => {
+				let stmts = stmts.iter().map(|stmt| stmt.map(&mut f)).collect();
+				ExprKind::Block(stmts, expr.as_ref().map(|expr| Box::new(f(expr))))
+			}
+			ExprKind::RecordFieldAccess(expr, ident) => ExprKind::RecordFieldAccess(Box::new(f(expr)), ident.clone()),
+			ExprKind::TupleFieldAccess(expr, number) => ExprKind::TupleFieldAccess(Box::new(f(expr)), *number),
+			ExprKind::OptionalRecordFieldAccess(expr, ident) => {
+				ExprKind::OptionalRecordFieldAccess(Box::new(f(expr)), ident.clone())
+			}
+			ExprKind::OptionalTupleFieldAccess(expr, number) => {
+				ExprKind::OptionalTupleFieldAccess(Box::new(f(expr)), *number)
+			}
+			ExprKind::OptionalType(expr) => ExprKind::OptionalType(Box::new(f(expr))),
+			ExprKind::UnionType(lhs, rhs) => ExprKind::UnionType(Box::new(f(lhs)), Box::new(f(rhs))),
+			ExprKind::Is(expr, ty) => ExprKind::Is(Box::new(f(expr)), Box::new(f(ty))),
+			ExprKind::Interpolated(parts) => ExprKind::Interpolated(parts.iter().map(|part| match part {
+				StringPart::Literal(s) => StringPart::Literal(s.clone()),
+				StringPart::Expr(expr) => StringPart::Expr(f(expr)),
+			}).collect()),
+			ExprKind::Parenthesized(expr) => ExprKind::Parenthesized(Box::new(f(expr))),
+			ExprKind::Negate(expr) => ExprKind::Negate(Box::new(f(expr))),
+			ExprKind::BinOp(op, lhs, rhs) => ExprKind::BinOp(*op, Box::new(f(lhs)), Box::new(f(rhs))),
+			ExprKind::Call(function, args) => {
+				let function = Box::new(f(function));
+				ExprKind::Call(function, args.iter().map(f).collect())
+			}
+			ExprKind::Coalesce(lhs, rhs) => ExprKind::Coalesce(Box::new(f(lhs)), Box::new(f(rhs))),
+			ExprKind::If(condition, then, otherwise) => ExprKind::If(
+				Box::new(f(condition)),
+				Box::new(f(then)),
+				otherwise.as_ref().map(|otherwise| Box::new(f(otherwise))),
+			),
+			ExprKind::IfLet(ident, value, then, otherwise) => ExprKind::IfLet(
+				ident.clone(),
+				Box::new(f(value)),
+				Box::new(f(then)),
+				otherwise.as_ref().map(|otherwise| Box::new(f(otherwise))),
+			),
+			ExprKind::Lambda(params, body) => ExprKind::Lambda(params.clone(), Box::new(f(body))),
+			ExprKind::FunctionType(params, ret) => {
+				ExprKind::FunctionType(params.iter().map(&mut f).collect(), Box::new(f(ret)))
+			}
+			ExprKind::Coerce(expr, ty) => ExprKind::Coerce(Box::new(f(expr)), ty.clone()),
+			ExprKind::Nil
+			| ExprKind::NilType
+			| ExprKind::Universe(_)
+			| ExprKind::Var(_)
+			| ExprKind::IntLiteral(_)
+			| ExprKind::FloatLiteral(_)
+			| ExprKind::StringLiteral(_) => self.kind.clone(),
+		};
+
+		Expr {kind, span: self.span.clone()}
+	}
+}
+
+/// Expressions are shown the way they're written, except for the parts that
+/// the type checker added
+impl fmt::Display for Expr {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let optional = |expr: &Option<Box<Expr>>| expr.as_ref().map(|expr| expr.to_string()).unwrap_or_default();
+
+		match &self.kind {
+			ExprKind::Nil => write!(fmt, "{{}}"),
+			ExprKind::NilType => write!(fmt, "type {{}}"),
+			ExprKind::Universe(0) => write!(fmt, "Type"),
+			ExprKind::Universe(level) => write!(fmt, "Type {}", level),
+			ExprKind::RecordValue(pairs) => write!(fmt, "{{{}}}", join(", ", pairs.iter().map(|(ident, expr)| (ident, expr)).map(mapping(" = ")))),
+			ExprKind::RecordType(pairs) => write!(fmt, "{{{}}}", join(", ", pairs.iter().map(|(ident, expr)| (ident, expr)).map(mapping(": ")))),
+			ExprKind::RecordExtension(record, pairs) => {
+				write!(fmt, "{{{} with {}}}", record, join(", ", pairs.iter().map(|(ident, expr)| (ident, expr)).map(mapping(" = "))))
+			}
+			ExprKind::RecordRemoval(record, idents) => write!(fmt, "{{{} without {}}}", record, join(", ", idents)),
+			ExprKind::Tuple(exprs) => write!(fmt, "({})", join(", ", exprs)),
+			ExprKind::TupleType(elements) => {
+				let elements = elements.iter().map(|(ident, expr)| match ident {
+					Some(ident) => format!("{}: {}", ident, expr),
+					None => expr.to_string(),
+				});
+				write!(fmt, "type ({})", join(", ", elements))
+			}
+			ExprKind::List(exprs) => write!(fmt, "[{}]", join(", ", exprs)),
+			ExprKind::ListType(expr) => write!(fmt, "List {}", expr),
+			ExprKind::Map(entries) if entries.is_empty() => write!(fmt, "[:]"),
+			ExprKind::Map(entries) => write!(fmt, "[{}]", join(", ", entries.iter().map(|(key, value)| (key, value)).map(mapping(": ")))),
+			ExprKind::MapType(key, value) => write!(fmt, "Map {} {}", key, value),
+			ExprKind::Set(exprs) => write!(fmt, "#[{}]", join(", ", exprs)),
+			ExprKind::SetType(expr) => write!(fmt, "Set {}", expr),
+			ExprKind::Block(stmts, expr) => {
+				write!(fmt, "{{")?;
+				for stmt in stmts {
+					write!(fmt, "{}; ", stmt)?;
+				}
+				write!(fmt, "{}}}", optional(expr).trim_end())
+			}
+			ExprKind::Var(ident) => write!(fmt, "{}", ident),
+			ExprKind::RecordFieldAccess(expr, ident) => write!(fmt, "{}.{}", expr, ident),
+			ExprKind::TupleFieldAccess(expr, number) => write!(fmt, "{}.{}", expr, number),
+			ExprKind::OptionalRecordFieldAccess(expr, ident) => write!(fmt, "{}?.{}", expr, ident),
+			ExprKind::OptionalTupleFieldAccess(expr, number) => write!(fmt, "{}?.{}", expr, number),
+			ExprKind::OptionalType(expr) => write!(fmt, "{}?", expr),
+			ExprKind::UnionType(lhs, rhs) => write!(fmt, "{} | {}", lhs, rhs),
+			ExprKind::Is(expr, ty) => write!(fmt, "{} is {}", expr, ty),
+			ExprKind::IntLiteral(int) => write!(fmt, "{}", int),
+			ExprKind::FloatLiteral(float) => write!(fmt, "{:?}", float),
+			ExprKind::StringLiteral(s) => write!(fmt, "\"{}\"", escape(s)),
+			ExprKind::Interpolated(parts) => {
+				write!(fmt, "\"")?;
+				for part in parts {
+					match part {
+						StringPart::Literal(s) => write!(fmt, "{}", escape(s))?,
+						StringPart::Expr(expr) => write!(fmt, "${{{}}}", expr)?,
+					}
+				}
+				write!(fmt, "\"")
+			}
+			ExprKind::Parenthesized(expr) => write!(fmt, "({})", expr),
+			ExprKind::Negate(expr) => write!(fmt, "-{}", expr),
+			ExprKind::BinOp(op, lhs, rhs) => write!(fmt, "{} {} {}", lhs, op, rhs),
+			ExprKind::Call(function, args) => write!(fmt, "{}({})", function, join(", ", args)),
+			ExprKind::Coalesce(lhs, rhs) => write!(fmt, "{} ?? {}", lhs, rhs),
+			ExprKind::If(condition, then, None) => write!(fmt, "if {} {}", condition, then),
+			ExprKind::If(condition, then, Some(otherwise)) => {
+				write!(fmt, "if {} {} else {}", condition, then, otherwise)
+			}
+			ExprKind::IfLet(ident, value, then, None) => write!(fmt, "if let {} = {} {}", ident, value, then),
+			ExprKind::IfLet(ident, value, then, Some(otherwise)) => {
+				write!(fmt, "if let {} = {} {} else {}", ident, value, then, otherwise)
+			}
+			ExprKind::Lambda(params, body) => write!(fmt, "|{}| {}", join(", ", params), body),
+			ExprKind::FunctionType(params, ret) => write!(fmt, "({}) -> {}", join(", ", params), ret),
+			ExprKind::Coerce(expr, ty) => write!(fmt, "({} as {})", expr, ty),
+		}
+	}
 }
 
 #[derive(Debug, Clone)]
 pub enum ExprKind {
 	Nil,
 	NilType,
+	/// `Type`, or `Type n` for the universes above it: `Type : Type 1 : Type 2 ...`
+	Universe(u32),
 	RecordValue(Vec<(Ident, Expr)>),
 	RecordType(Vec<(Ident, Expr)>),
+	/// `{record with field = value}`, a copy of a record with fields added or replaced
+	RecordExtension(Box<Expr>, Vec<(Ident, Expr)>),
+	/// `{record without field}`, a copy of a record without some of its fields
+	RecordRemoval(Box<Expr>, Vec<Ident>),
 	Tuple(Vec<Expr>),
-	TupleType(Vec<Expr>),
+	/// `type (T: Type, List T)`. Elements can be named so that the types
+	/// of later elements can depend on them
+	TupleType(Vec<(Option<Ident>, Expr)>),
+	List(Vec<Expr>),
+	/// `List T`
+	ListType(Box<Expr>),
+	/// `[key: value, ...]`, or `[:]` for an empty map
+	Map(Vec<(Expr, Expr)>),
+	/// `Map K V`
+	MapType(Box<Expr>, Box<Expr>),
+	/// `#[element, ...]`
+	Set(Vec<Expr>),
+	/// `Set T`
+	SetType(Box<Expr>),
 	Block(Vec<Stmt>, Option<Box<Expr>>),
 	Var(Ident),
 	RecordFieldAccess(Box<Expr>, Ident),
 	TupleFieldAccess(Box<Expr>, usize),
-	NumberLiteral(Number),
+	/// `expr?.field`, which is `none` if `expr` is
+	OptionalRecordFieldAccess(Box<Expr>, Ident),
+	/// `expr?.0`, which is `none` if `expr` is
+	OptionalTupleFieldAccess(Box<Expr>, usize),
+	/// `T?`, the type of values of type `T` that might be missing
+	OptionalType(Box<Expr>),
+	/// `A | B`, the type of values that are either an `A` or a `B`
+	UnionType(Box<Expr>, Box<Expr>),
+	/// `value is T`, which checks while the program runs whether `value` is a `T`
+	Is(Box<Expr>, Box<Expr>),
+	IntLiteral(BigInt),
+	FloatLiteral(f64),
 	StringLiteral(String),
+	/// A string literal with `${...}` expressions in it
+	Interpolated(Vec<StringPart>),
 	Parenthesized(Box<Expr>),
+	Negate(Box<Expr>),
+	BinOp(BinOp, Box<Expr>, Box<Expr>),
+	Call(Box<Expr>, Vec<Expr>),
+	/// `lhs ?? rhs`: the value in the optional `lhs` if there is one, otherwise `rhs`
+	Coalesce(Box<Expr>, Box<Expr>),
+	If(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+	/// `if let name = optional {...} else {...}`
+	IfLet(Ident, Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+	/// `|x, y| body`
+	Lambda(Vec<Ident>, Box<Expr>),
+	/// `(A, B) -> C`
+	FunctionType(Vec<Expr>, Box<Expr>),
+	/// A value used as a value of a union type that it's a member of. The type
+	/// checker puts these in; they can't be written in the source.
+	Coerce(Box<Expr>, Type),
+}
+
+#[derive(Debug, Display, Clone, Copy, Eq, PartialEq)]
+pub enum BinOp {
+	#[display(fmt = "+")]
+	Add,
+	#[display(fmt = "-")]
+	Sub,
+	#[display(fmt = "*")]
+	Mul,
+	#[display(fmt = "/")]
+	Div,
+	#[display(fmt = "++")]
+	Concat,
+}
+
+#[derive(Debug, Clone)]
+pub enum StringPart {
+	Literal(String),
+	Expr(Expr),
 }
 
 #[derive(Debug, Clone)]
@@ -76,11 +312,86 @@ impl Stmt {
 	pub fn new((kind, span): (StmtKind, Span)) -> Self {
 		Self {kind, span}
 	}
+
+	/// Returns a copy of this statement with `f` applied to the expressions in it
+	pub fn map(&self, mut f: impl FnMut(&Expr) -> Expr) -> Stmt {
+		let kind = match &self.kind {
+			StmtKind::Let(ident, annotation, expr) => StmtKind::Let(
+				ident.clone(),
+				annotation.as_ref().map(|annotation| Box::new(f(annotation))),
+				Box::new(f(expr)),
+			),
+			StmtKind::LetMut(ident, annotation, expr) => StmtKind::LetMut(
+				ident.clone(),
+				annotation.as_ref().map(|annotation| Box::new(f(annotation))),
+				Box::new(f(expr)),
+			),
+			StmtKind::Assign(ident, expr) => StmtKind::Assign(ident.clone(), Box::new(f(expr))),
+			StmtKind::Type(ident, expr) => StmtKind::Type(ident.clone(), Box::new(f(expr))),
+			StmtKind::Trait(ident, methods) => StmtKind::Trait(
+				ident.clone(),
+				methods.iter().map(|(name, params, ret)| {
+					(name.clone(), params.iter().map(&mut f).collect(), f(ret))
+				}).collect(),
+			),
+			StmtKind::Impl(ident, ty, methods) => StmtKind::Impl(
+				ident.clone(),
+				Box::new(f(ty)),
+				methods.iter().map(|(name, expr)| (name.clone(), f(expr))).collect(),
+			),
+			StmtKind::Import(path, ident) => StmtKind::Import(path.clone(), ident.clone()),
+		};
+
+		Stmt {kind, span: self.span.clone()}
+	}
+}
+
+impl fmt::Display for Stmt {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.kind {
+			StmtKind::Let(ident, None, expr) => write!(fmt, "let {} = {}", ident, expr),
+			StmtKind::Let(ident, Some(annotation), expr) => {
+				write!(fmt, "let {}: {} = {}", ident, annotation, expr)
+			}
+			StmtKind::LetMut(ident, None, expr) => write!(fmt, "let mut {} = {}", ident, expr),
+			StmtKind::LetMut(ident, Some(annotation), expr) => {
+				write!(fmt, "let mut {}: {} = {}", ident, annotation, expr)
+			}
+			StmtKind::Assign(ident, expr) => write!(fmt, "{} = {}", ident, expr),
+			StmtKind::Type(ident, expr) => write!(fmt, "type {} = {}", ident, expr),
+			StmtKind::Trait(ident, methods) => {
+				let methods = methods.iter().map(|(name, params, ret)| {
+					format!("{}: ({}) -> {}", name, join(", ", params), ret)
+				});
+				write!(fmt, "trait {} {{{}}}", ident, join(", ", methods))
+			}
+			StmtKind::Impl(ident, ty, methods) => {
+				let methods = methods.iter().map(|(name, expr)| (name, expr)).map(mapping(" = "));
+				write!(fmt, "impl {} for {} {{{}}}", ident, ty, join(", ", methods))
+			}
+			StmtKind::Import(path, ident) => write!(fmt, "import \"{}\" as {}", escape(path), ident),
+		}
+	}
 }
 
 #[derive(Debug, Clone)]
 pub enum StmtKind {
-	Let(Ident, Box<Expr>),
+	/// `let name: T = value`, where the type annotation is optional
+	Let(Ident, Option<Box<Expr>>, Box<Expr>),
+	/// `let mut name: T = value`, a variable that can be assigned to
+	LetMut(Ident, Option<Box<Expr>>, Box<Expr>),
+	/// `name = value`, which changes the value of a `let mut` variable
+	Assign(Ident, Box<Expr>),
+	/// `type Name = T`, which gives a type a name. `T` can refer to `Name`
+	Type(Ident, Box<Expr>),
+	/// `trait Name {method: (Self, A) -> B}`, which declares methods that
+	/// types can implement. `Self` is the implementing type.
+	Trait(Ident, Vec<(Ident, Vec<Expr>, Expr)>),
+	/// `impl Trait for T {method = value}`
+	Impl(Ident, Box<Expr>, Vec<(Ident, Expr)>),
+	/// `import "path/to/file.lang" as M`, which binds `M` to a record of the
+	/// names defined in the file. The path is relative to the importing file.
+	Import(String, Ident),
 }
 
 #[derive(Debug, Display, Clone)]
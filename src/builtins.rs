@@ -0,0 +1,769 @@
+use {
+    crate::{
+        ast::{BinOp, Name},
+        context::{TypeContext, ValueContext},
+        graphql::Schema,
+        json,
+        typeck::{Type, TypeBinding, default_dictionary_name, dictionary_name, dictionary_type, trait_binding_name},
+        util::{OrderedMap, join},
+        vm::{self, Value, Builtin, VmError},
+    },
+    im::Vector,
+    num_bigint::BigInt,
+    num_traits::{Signed, ToPrimitive, FromPrimitive},
+    std::{path::Path, sync::Arc},
+};
+
+macro_rules! builtin_error {
+    ($($tt:tt)*) => {
+        return Err(VmError::new(format!($($tt)*)))
+    };
+}
+
+/// A set of named values, usually functions implemented in Rust, along with their types.
+/// They can be added to a TypeContext and ValueContext to make them available to programs.
+#[derive(Default, Clone)]
+pub struct Builtins {
+    entries: Vec<(Name, Type, Value)>,
+}
+
+impl Builtins {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The builtins that every program starts out with
+    pub fn standard() -> Self {
+        let a = || Type::Param("a".into());
+        let b = || Type::Param("b".into());
+        let k = || Type::Param("k".into());
+        let v = || Type::Param("v".into());
+        let list = |ty| Type::List(Box::new(ty));
+        let map_type = || Type::Map(Box::new(k()), Box::new(v()));
+        let set_type = || Type::Set(Box::new(a()));
+        let self_type = || Type::Param("Self".into());
+
+        let mut builtins = Self::new();
+
+        builtins
+            .define("nil", Type::Nil, Value::Nil)
+            .define_type("Nil", Type::Nil)
+            .define_type("Int", Type::Int)
+            .define_type("Float", Type::Float)
+            .define_type("String", Type::String_)
+            .define_type("Bool", Type::Bool)
+            .define("true", Type::Bool, Value::Bool(true))
+            .define("false", Type::Bool, Value::Bool(false))
+
+            .define("none", forall(&["a"], Type::Optional(Box::new(a()))), Value::Optional(None))
+            .register("some", forall(&["a"], function(vec![a()], Type::Optional(Box::new(a())))), some)
+            .register("Option", function(vec![Type::Type(0)], Type::Type(0)), option_type)
+
+            // `Self` is the implementing type in the types of a trait's methods
+            .define_trait("Show", vec![("to_string", function(vec![self_type()], Type::String_))])
+            .implement("Show", None, vec![("to_string", builtin("to_string", to_string))])
+            .define_trait("Eq", vec![("equals", function(vec![self_type(), self_type()], Type::Bool))])
+            .implement("Eq", None, vec![("equals", builtin("equals", equals))])
+            // takes the implementation of Show to print the value with
+            .register("print", constrained(&[("Show", a())], function(vec![a()], Type::Nil)), print)
+            .define_trait("Add", vec![("plus", function(vec![self_type(), self_type()], self_type()))])
+            .implement("Add", Some(Type::Int), vec![("plus", builtin("plus", binop(BinOp::Add)))])
+            .implement("Add", Some(Type::Float), vec![("plus", builtin("plus", binop(BinOp::Add)))])
+            .implement("Add", Some(Type::String_), vec![("plus", builtin("plus", binop(BinOp::Add)))])
+            .register("to_json", forall(&["a"], function(vec![a()], Type::String_)), to_json)
+            // returns a value of the type that's passed to it, which is `.1`. That
+            // type can be in `Type 1`, so that dependent records can be read
+            .register("from_json", function(vec![Type::String_, Type::Type(1)], Type::Field("1".into())), from_json)
+
+            .register("to_float", function(vec![Type::Int], Type::Float), int_to_float)
+            .register("to_int", function(vec![Type::Float], Type::Int), float_to_int)
+            .register("abs", function(vec![Type::Int], Type::Int), abs)
+            .register("min", function(vec![Type::Int, Type::Int], Type::Int), min)
+            .register("max", function(vec![Type::Int, Type::Int], Type::Int), max)
+            .register("pow", function(vec![Type::Int, Type::Int], Type::Int), pow)
+            .register("sqrt", function(vec![Type::Float], Type::Float), float_fn("sqrt", f64::sqrt))
+            .register("floor", function(vec![Type::Float], Type::Float), float_fn("floor", f64::floor))
+            .register("ceil", function(vec![Type::Float], Type::Float), float_fn("ceil", f64::ceil))
+            .register("round", function(vec![Type::Float], Type::Float), float_fn("round", f64::round))
+
+            .register("length", function(vec![Type::String_], Type::Int), string_length)
+            .register(
+                "substring",
+                function(vec![Type::String_, Type::Int, Type::Int], Type::String_),
+                substring,
+            )
+            .register("contains", function(vec![Type::String_, Type::String_], Type::Bool), string_contains)
+            .register("to_upper", function(vec![Type::String_], Type::String_), to_upper)
+            .register("to_lower", function(vec![Type::String_], Type::String_), to_lower)
+            .register("split", function(vec![Type::String_, Type::String_], list(Type::String_)), split)
+
+            .register("len", forall(&["a"], function(vec![list(a())], Type::Int)), list_len)
+            .register(
+                "get",
+                forall(&["a"], function(vec![list(a()), Type::Int], Type::Optional(Box::new(a())))),
+                list_get,
+            )
+            .register("push", forall(&["a"], function(vec![list(a()), a()], list(a()))), push)
+            .register("concat", forall(&["a"], function(vec![list(a()), list(a())], list(a()))), concat)
+            .register(
+                "map",
+                forall(&["a", "b"], function(vec![list(a()), function(vec![a()], b())], list(b()))),
+                map,
+            )
+            .register(
+                "filter",
+                forall(&["a"], function(vec![list(a()), function(vec![a()], Type::Bool)], list(a()))),
+                filter,
+            )
+            .register(
+                "fold",
+                forall(&["a", "b"], function(vec![list(a()), b(), function(vec![b(), a()], b())], b())),
+                fold,
+            )
+
+            .register("insert", forall(&["k", "v"], function(vec![map_type(), k(), v()], map_type())), map_insert)
+            .register("remove", forall(&["k", "v"], function(vec![map_type(), k()], map_type())), map_remove)
+            .register(
+                "lookup",
+                forall(&["k", "v"], function(vec![map_type(), k()], Type::Optional(Box::new(v())))),
+                map_lookup,
+            )
+            .register("has_key", forall(&["k", "v"], function(vec![map_type(), k()], Type::Bool)), map_has_key)
+            .register("keys", forall(&["k", "v"], function(vec![map_type()], list(k()))), map_keys)
+            .register("values", forall(&["k", "v"], function(vec![map_type()], list(v()))), map_values)
+            .register(
+                "entries",
+                forall(&["k", "v"], function(vec![map_type()], list(Type::Tuple(vec![k(), v()])))),
+                map_entries,
+            )
+            .register("merge", forall(&["k", "v"], function(vec![map_type(), map_type()], map_type())), map_merge)
+
+            .register("add", forall(&["a"], function(vec![set_type(), a()], set_type())), set_add)
+            .register("discard", forall(&["a"], function(vec![set_type(), a()], set_type())), set_discard)
+            .register("member", forall(&["a"], function(vec![set_type(), a()], Type::Bool)), set_member)
+            .register("union", forall(&["a"], function(vec![set_type(), set_type()], set_type())), set_union)
+            .register("intersection", forall(&["a"], function(vec![set_type(), set_type()], set_type())), set_intersection)
+            .register("difference", forall(&["a"], function(vec![set_type(), set_type()], set_type())), set_difference)
+            .register("elements", forall(&["a"], function(vec![set_type()], list(a()))), set_elements)
+            .register("to_set", forall(&["a"], function(vec![list(a())], set_type())), to_set)
+
+            .extend(&Self::graphql(None));
+
+        builtins
+    }
+
+    /// `graphql_type` and `graphql_query`, which read schema files relative to
+    /// `directory`, or the current directory if it's None
+    pub fn graphql(directory: Option<&Path>) -> Self {
+        let directory = directory.unwrap_or_else(|| Path::new("")).to_path_buf();
+        let type_directory = directory.clone();
+        let mut builtins = Self::new();
+
+        builtins
+            .register(
+                "graphql_type",
+                function(vec![Type::String_, Type::String_], Type::Type(0)),
+                move |args| graphql_type(&type_directory, args),
+            )
+            .register(
+                "graphql_query",
+                function(vec![Type::String_, Type::String_], Type::Type(0)),
+                move |args| graphql_query(&directory, args),
+            );
+
+        builtins
+    }
+
+    /// Adds all of `other`'s entries
+    pub fn extend(&mut self, other: &Builtins) -> &mut Self {
+        self.entries.extend(other.entries.iter().cloned());
+        self
+    }
+
+    /// Adds a value with the given type. It's up to the caller to make sure the value
+    /// actually has that type.
+    pub fn define(&mut self, name: &str, ty: Type, value: Value) -> &mut Self {
+        self.entries.push((name.into(), ty, value));
+        self
+    }
+
+    /// Adds a type, e.g. `Int`
+    pub fn define_type(&mut self, name: &str, ty: Type) -> &mut Self {
+        self.define(name, Type::Type(ty.level()), Value::Type(ty))
+    }
+
+    /// Adds a function implemented in Rust. `ty` has to be a function type, or a
+    /// polymorphic function type, and the type checker will make sure that
+    /// `function` is only called with arguments of the right types.
+    pub fn register(
+        &mut self,
+        name: &str,
+        ty: Type,
+        function: impl Fn(Vec<Value>) -> Result<Value, VmError> + Send + Sync + 'static,
+    ) -> &mut Self {
+        let is_function = match &ty {
+            Type::Forall(_, ty) => match &**ty {
+                Type::Constrained(_, ty) => matches!(**ty, Type::Function(..)),
+                ty => matches!(ty, Type::Function(..)),
+            },
+            ty => matches!(ty, Type::Function(..)),
+        };
+        assert!(is_function, "builtin {} should have a function type, not {}", name, ty);
+
+        self.define(name, ty, builtin(name, function))
+    }
+
+    /// Adds a trait and its methods. `Self` stands for the implementing type
+    /// in the types of the methods.
+    pub fn define_trait(&mut self, name: &str, methods: Vec<(&str, Type)>) -> &mut Self {
+        let trait_name = Name::from(name);
+        let methods: OrderedMap<Name, Type> = methods.into_iter()
+            .map(|(method, ty)| (method.into(), ty))
+            .collect();
+
+        self.entries.push((trait_binding_name(&trait_name), Type::Record(methods.clone(), None), Value::Nil));
+        for (method, ty) in methods {
+            self.entries.push((method.clone(), Type::method(&trait_name, ty), Value::Builtin(Builtin::method(method))));
+        }
+        self
+    }
+
+    /// Adds an implementation of a trait that was added before, for `ty`, or for
+    /// every type that doesn't have one of its own if `ty` is None. As with
+    /// `define`, it's up to the caller to make sure the methods have the right types.
+    pub fn implement(&mut self, trait_name: &str, ty: Option<Type>, methods: Vec<(&str, Value)>) -> &mut Self {
+        let trait_name = Name::from(trait_name);
+        let trait_type = self.entries.iter()
+            .find(|(name, _, _)| *name == trait_binding_name(&trait_name))
+            .map(|(_, ty, _)| ty.clone())
+            .unwrap_or_else(|| panic!("trait {} should be added before it's implemented", trait_name));
+
+        let (name, ty) = match ty {
+            Some(ty) => (dictionary_name(&trait_name, &ty), dictionary_type(&trait_type, &ty)),
+            None => (default_dictionary_name(&trait_name), trait_type),
+        };
+        let dictionary = methods.into_iter().map(|(method, value)| (method.into(), value)).collect();
+
+        self.entries.push((name, ty, Value::Record(dictionary)));
+        self
+    }
+
+    /// Returns the given contexts with all of the builtins added to them
+    pub fn add_to(
+        &self,
+        type_context: &TypeContext,
+        value_context: &ValueContext,
+    ) -> (TypeContext, ValueContext) {
+        self.entries.iter().fold(
+            (type_context.clone(), value_context.clone()),
+            |(type_context, value_context), (name, ty, value)| (
+                type_context.extend(name.clone(), TypeBinding::for_value(ty.clone(), value)),
+                value_context.extend(name.clone(), value.clone()),
+            ),
+        )
+    }
+}
+
+pub fn function(params: Vec<Type>, ret: Type) -> Type {
+    Type::Function(params, Box::new(ret))
+}
+
+pub fn forall(params: &[&str], ty: Type) -> Type {
+    Type::Forall(params.iter().map(|&param| param.into()).collect(), Box::new(ty))
+}
+
+/// The type of a function that needs implementations of traits for its type
+/// parameters, which are `a`, `b`, ... in the order they're used in `constraints`.
+/// It takes their dictionaries before its own arguments.
+pub fn constrained(constraints: &[(&str, Type)], ty: Type) -> Type {
+    let mut params: Vec<Name> = Vec::new();
+    for (_, ty) in constraints {
+        if let Type::Param(param) = ty {
+            if !params.contains(param) {
+                params.push(param.clone());
+            }
+        }
+    }
+
+    let constraints = constraints.iter().map(|(trait_name, ty)| (Name::from(*trait_name), ty.clone())).collect();
+    Type::Forall(params, Box::new(Type::Constrained(constraints, Box::new(ty))))
+}
+
+/// A function implemented in Rust, as a value
+pub fn builtin(name: &str, function: impl Fn(Vec<Value>) -> Result<Value, VmError> + Send + Sync + 'static) -> Value {
+    Value::Builtin(Builtin {
+        name: name.into(),
+        function: Arc::new(function),
+    })
+}
+
+/// The type checker makes sure builtins are called with the right arguments,
+/// so this only happens if a builtin was registered with the wrong type
+fn unexpected_arguments(name: &str, args: &[Value]) -> Result<Value, VmError> {
+    builtin_error!("{} can't be called with ({})", name, join(", ", args.iter()))
+}
+
+/// Takes the dictionary of an implementation of Show, and returns the function
+/// that prints values with it
+fn print(args: Vec<Value>) -> Result<Value, VmError> {
+    let to_string = match args.as_slice() {
+        [Value::Record(dictionary)] if dictionary.contains_key(&Name::from("to_string")) => {
+            dictionary[&Name::from("to_string")].clone()
+        }
+        _ => return unexpected_arguments("print", &args),
+    };
+
+    Ok(builtin("print", move |args| {
+        if args.len() != 1 {
+            return unexpected_arguments("print", &args);
+        }
+        match vm::call(&to_string, args)? {
+            Value::String_(s) => println!("{}", s),
+            value => builtin_error!("print: expected to_string to return a String, found {}", value),
+        }
+        Ok(Value::Nil)
+    }))
+}
+
+fn to_string(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [value] => Ok(Value::String_(value.to_unquoted_string())),
+        _ => unexpected_arguments("to_string", &args),
+    }
+}
+
+fn equals(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [lhs, rhs] => Ok(Value::Bool(structurally_equal(lhs, rhs))),
+        _ => unexpected_arguments("equals", &args),
+    }
+}
+
+/// Whether two values have the same shape and contents. Unlike map keys, floats
+/// are compared as numbers, so `nan` isn't equal to itself.
+fn structurally_equal(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Float(lhs), Value::Float(rhs)) => lhs == rhs,
+        (Value::Optional(Some(lhs)), Value::Optional(Some(rhs))) => structurally_equal(lhs, rhs),
+        (Value::Tuple(lhs), Value::Tuple(rhs)) => {
+            lhs.len() == rhs.len() && lhs.iter().zip(rhs).all(|(lhs, rhs)| structurally_equal(lhs, rhs))
+        }
+        (Value::List(lhs), Value::List(rhs)) => {
+            lhs.len() == rhs.len() && lhs.iter().zip(rhs).all(|(lhs, rhs)| structurally_equal(lhs, rhs))
+        }
+        // records and maps are equal whatever order their fields or keys are in
+        (Value::Record(lhs), Value::Record(rhs)) => lhs.len() == rhs.len() && lhs.iter().all(|(name, lhs)| {
+            rhs.get(name).is_some_and(|rhs| structurally_equal(lhs, rhs))
+        }),
+        (Value::Map(lhs), Value::Map(rhs)) => lhs.len() == rhs.len() && lhs.iter().all(|(key, lhs)| {
+            rhs.get(key).is_some_and(|rhs| structurally_equal(lhs, rhs))
+        }),
+        (lhs, rhs) => lhs == rhs,
+    }
+}
+
+fn binop(op: BinOp) -> impl Fn(Vec<Value>) -> Result<Value, VmError> {
+    move |args| match args.as_slice() {
+        [lhs, rhs] => vm::evaluate_binop(op, lhs.clone(), rhs.clone()),
+        _ => unexpected_arguments(&op.to_string(), &args),
+    }
+}
+
+fn some(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [value] => Ok(Value::Optional(Some(Box::new(value.clone())))),
+        _ => unexpected_arguments("some", &args),
+    }
+}
+
+/// `Option(T)` is the same as `T?`
+fn option_type(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Type(ty)] => Ok(Value::Type(Type::Optional(Box::new(ty.clone())))),
+        _ => unexpected_arguments("Option", &args),
+    }
+}
+
+fn from_json(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(text), Value::Type(ty)] => match json::from_json(text, ty) {
+            Ok(value) => Ok(value),
+            Err(error) => builtin_error!("from_json: {}", error),
+        },
+        _ => unexpected_arguments("from_json", &args),
+    }
+}
+
+fn to_json(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [value] => match json::to_json(value) {
+            Ok(text) => Ok(Value::String_(text)),
+            Err(error) => builtin_error!("to_json: {}", error),
+        },
+        _ => unexpected_arguments("to_json", &args),
+    }
+}
+
+fn int_to_float(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Int(int)] => match int.to_f64() {
+            Some(float) if float.is_finite() => Ok(Value::Float(float)),
+            _ => builtin_error!("{} is too big to be converted to a Float", int),
+        },
+        _ => unexpected_arguments("to_float", &args),
+    }
+}
+
+/// Converts a float to an int, rounding towards zero
+fn float_to_int(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Float(float)] => match BigInt::from_f64(float.trunc()) {
+            Some(int) => Ok(Value::Int(int)),
+            None => builtin_error!("{:?} can't be converted to an Int", float),
+        },
+        _ => unexpected_arguments("to_int", &args),
+    }
+}
+
+fn abs(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Int(int)] => Ok(Value::Int(int.abs())),
+        _ => unexpected_arguments("abs", &args),
+    }
+}
+
+fn min(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a.min(b).clone())),
+        _ => unexpected_arguments("min", &args),
+    }
+}
+
+fn max(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a.max(b).clone())),
+        _ => unexpected_arguments("max", &args),
+    }
+}
+
+fn pow(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Int(base), Value::Int(exponent)] => match exponent.to_usize() {
+            Some(exponent) => Ok(Value::Int(num_traits::pow(base.clone(), exponent))),
+            None => builtin_error!("can't raise {} to the power of {}", base, exponent),
+        },
+        _ => unexpected_arguments("pow", &args),
+    }
+}
+
+/// Makes a builtin out of a function from Float to Float
+fn float_fn(name: &'static str, f: fn(f64) -> f64) -> impl Fn(Vec<Value>) -> Result<Value, VmError> {
+    move |args| match args.as_slice() {
+        [Value::Float(float)] => Ok(Value::Float(f(*float))),
+        _ => unexpected_arguments(name, &args),
+    }
+}
+
+fn string_length(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(s)] => Ok(Value::Int(s.chars().count().into())),
+        _ => unexpected_arguments("length", &args),
+    }
+}
+
+/// Returns the characters of a string from `start` up to but not including `end`
+fn substring(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(s), Value::Int(start), Value::Int(end)] => {
+            let length = s.chars().count();
+
+            match (start.to_usize(), end.to_usize()) {
+                (Some(start), Some(end)) if start <= end && end <= length => {
+                    Ok(Value::String_(s.chars().skip(start).take(end - start).collect()))
+                }
+                _ => builtin_error!(
+                    "substring range {}..{} is out of bounds for string of length {}",
+                    start, end, length,
+                ),
+            }
+        }
+        _ => unexpected_arguments("substring", &args),
+    }
+}
+
+fn string_contains(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(s), Value::String_(substring)] => Ok(Value::Bool(s.contains(substring.as_str()))),
+        _ => unexpected_arguments("contains", &args),
+    }
+}
+
+fn to_upper(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(s)] => Ok(Value::String_(s.to_uppercase())),
+        _ => unexpected_arguments("to_upper", &args),
+    }
+}
+
+fn to_lower(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(s)] => Ok(Value::String_(s.to_lowercase())),
+        _ => unexpected_arguments("to_lower", &args),
+    }
+}
+
+/// Splits a string into the parts between occurrences of `separator`
+fn split(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(s), Value::String_(separator)] => {
+            if separator.is_empty() {
+                builtin_error!("split: separator can't be empty")
+            }
+            Ok(Value::List(s.split(separator.as_str()).map(|part| Value::String_(part.to_string())).collect()))
+        }
+        _ => unexpected_arguments("split", &args),
+    }
+}
+
+fn list_len(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::List(list)] => Ok(Value::Int(BigInt::from(list.len()))),
+        _ => unexpected_arguments("len", &args),
+    }
+}
+
+/// The element at `index`, or `none` if it is out of range
+fn list_get(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::List(list), Value::Int(index)] => {
+            let element = index.to_usize().and_then(|index| list.get(index));
+            Ok(Value::Optional(element.map(|element| Box::new(element.clone()))))
+        }
+        _ => unexpected_arguments("get", &args),
+    }
+}
+
+fn push(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::List(list), value] => {
+            let mut list = list.clone();
+            list.push_back(value.clone());
+            Ok(Value::List(list))
+        }
+        _ => unexpected_arguments("push", &args),
+    }
+}
+
+fn concat(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::List(first), Value::List(second)] => {
+            let mut list = first.clone();
+            list.append(second.clone());
+            Ok(Value::List(list))
+        }
+        _ => unexpected_arguments("concat", &args),
+    }
+}
+
+fn map(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::List(list), function] => {
+            list.iter()
+                .map(|element| vm::call(function, vec![element.clone()]))
+                .collect::<Result<_, _>>()
+                .map(Value::List)
+        }
+        _ => unexpected_arguments("map", &args),
+    }
+}
+
+fn filter(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::List(list), predicate] => {
+            let mut filtered = Vector::new();
+            for element in list {
+                match vm::call(predicate, vec![element.clone()])? {
+                    Value::Bool(true) => filtered.push_back(element.clone()),
+                    Value::Bool(false) => (),
+                    value => builtin_error!("filter: expected the predicate to return a Bool, found {}", value),
+                }
+            }
+            Ok(Value::List(filtered))
+        }
+        _ => unexpected_arguments("filter", &args),
+    }
+}
+
+/// Combines the elements of a list, starting with `initial`: `fold([a, b], x, f)` is `f(f(x, a), b)`
+fn fold(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::List(list), initial, function] => {
+            list.iter().try_fold(initial.clone(), |accumulator, element| {
+                vm::call(function, vec![accumulator, element.clone()])
+            })
+        }
+        _ => unexpected_arguments("fold", &args),
+    }
+}
+
+/// Returns a copy of the map with `key` set to `value`, replacing any value it had before
+fn map_insert(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Map(map), key, value] => {
+            let mut map = map.clone();
+            map.insert(key.clone(), value.clone());
+            Ok(Value::Map(map))
+        }
+        _ => unexpected_arguments("insert", &args),
+    }
+}
+
+fn map_remove(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Map(map), key] => {
+            // rebuild the map rather than swap-removing, to keep the other keys in order
+            Ok(Value::Map(map.iter()
+                .filter(|(k, _)| *k != key)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()))
+        }
+        _ => unexpected_arguments("remove", &args),
+    }
+}
+
+fn map_lookup(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Map(map), key] => Ok(Value::Optional(map.get(key).map(|value| Box::new(value.clone())))),
+        _ => unexpected_arguments("lookup", &args),
+    }
+}
+
+fn map_has_key(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Map(map), key] => Ok(Value::Bool(map.contains_key(key))),
+        _ => unexpected_arguments("has_key", &args),
+    }
+}
+
+fn map_keys(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Map(map)] => Ok(Value::List(map.keys().cloned().collect())),
+        _ => unexpected_arguments("keys", &args),
+    }
+}
+
+fn map_values(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Map(map)] => Ok(Value::List(map.values().cloned().collect())),
+        _ => unexpected_arguments("values", &args),
+    }
+}
+
+fn map_entries(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Map(map)] => {
+            Ok(Value::List(map.iter().map(|(k, v)| Value::Tuple(vec![k.clone(), v.clone()])).collect()))
+        }
+        _ => unexpected_arguments("entries", &args),
+    }
+}
+
+/// Combines two maps. Where they have the same key, the value from the second one wins.
+fn map_merge(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Map(first), Value::Map(second)] => {
+            let mut map = first.clone();
+            map.extend(second.iter().map(|(k, v)| (k.clone(), v.clone())));
+            Ok(Value::Map(map))
+        }
+        _ => unexpected_arguments("merge", &args),
+    }
+}
+
+fn set_add(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Set(set), element] => {
+            let mut set = set.clone();
+            set.insert(element.clone());
+            Ok(Value::Set(set))
+        }
+        _ => unexpected_arguments("add", &args),
+    }
+}
+
+fn set_discard(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Set(set), element] => Ok(Value::Set(set.iter().filter(|e| *e != element).cloned().collect())),
+        _ => unexpected_arguments("discard", &args),
+    }
+}
+
+fn set_member(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Set(set), element] => Ok(Value::Bool(set.contains(element))),
+        _ => unexpected_arguments("member", &args),
+    }
+}
+
+fn set_union(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Set(first), Value::Set(second)] => Ok(Value::Set(first.union(second).cloned().collect())),
+        _ => unexpected_arguments("union", &args),
+    }
+}
+
+fn set_intersection(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Set(first), Value::Set(second)] => {
+            Ok(Value::Set(first.intersection(second).cloned().collect()))
+        }
+        _ => unexpected_arguments("intersection", &args),
+    }
+}
+
+fn set_difference(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Set(first), Value::Set(second)] => Ok(Value::Set(first.difference(second).cloned().collect())),
+        _ => unexpected_arguments("difference", &args),
+    }
+}
+
+fn set_elements(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::Set(set)] => Ok(Value::List(set.iter().cloned().collect())),
+        _ => unexpected_arguments("elements", &args),
+    }
+}
+
+fn to_set(args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::List(list)] => Ok(Value::Set(list.iter().cloned().collect())),
+        _ => unexpected_arguments("to_set", &args),
+    }
+}
+
+/// The type called `name` in the GraphQL schema in the file at `path`
+fn graphql_type(directory: &Path, args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(path), Value::String_(name)] => {
+            match Schema::from_file(directory.join(path)).and_then(|schema| schema.type_named(name)) {
+                Ok(ty) => Ok(Value::Type(ty)),
+                Err(error) => builtin_error!("{}", error),
+            }
+        }
+        _ => unexpected_arguments("graphql_type", &args),
+    }
+}
+
+/// The type of the result of `query` against the GraphQL schema in the file at `path`
+fn graphql_query(directory: &Path, args: Vec<Value>) -> Result<Value, VmError> {
+    match args.as_slice() {
+        [Value::String_(path), Value::String_(query)] => {
+            match Schema::from_file(directory.join(path)).and_then(|schema| schema.query_type(query)) {
+                Ok(ty) => Ok(Value::Type(ty)),
+                Err(error) => builtin_error!("{}", error),
+            }
+        }
+        _ => unexpected_arguments("graphql_query", &args),
+    }
+}
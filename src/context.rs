@@ -2,15 +2,25 @@ use {
     crate::{
         ast::Name,
         util::Map,
+        builtins::Builtins,
+        prelude,
         vm::Value,
-        typeck::Type,
+        typeck::TypeBinding,
     },
     lazy_static::lazy_static,
+    std::sync::Arc,
 };
 
-#[derive(Clone)]
+/// The variables in scope. The map is shared, so that cloning a context (which
+/// every closure does) doesn't copy the closures that are already in it.
 pub struct Context<Value> {
-    map: Map<Name, Value>,
+    map: Arc<Map<Name, Value>>,
+}
+
+impl<Value> Clone for Context<Value> {
+    fn clone(&self) -> Self {
+        Self {map: self.map.clone()}
+    }
 }
 
 impl<Value> Context<Value> {
@@ -19,56 +29,78 @@ impl<Value> Context<Value> {
         Default::default()
     }
 
+    /// A context with nothing in it, not even the builtins
+    pub fn empty() -> Self {
+        Self {map: Arc::new(Map::default())}
+    }
+
     pub fn extend(&self, name: Name, value: Value) -> Self
     where
         Value: Clone,
     {
-        let mut map = self.map.clone();
+        let mut map = (*self.map).clone();
         map.insert(name, value);
-        Self {map}
+        Self {map: Arc::new(map)}
     }
 
     pub fn lookup(&self, name: &Name) -> Option<&Value> {
         self.map.get(name)
     }
+
+    /// A context with the same names, bound to `f` applied to their values
+    pub fn map(&self, f: impl Fn(&Value) -> Value) -> Self {
+        let map = self.map.iter().map(|(name, value)| (name.clone(), f(value))).collect();
+        Self {map: Arc::new(map)}
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&Name, &Value)> {
+        self.map.iter()
+    }
 }
 
 pub type ValueContext = Context<Value>;
 
+/// The builtins and the prelude
 impl Default for ValueContext {
     fn default() -> Self {
         let (_, values) = &*DEFAULT_CONTEXTS;
-        Context {map: values.clone()}
+        values.clone()
     }
 }
 
-pub type TypeContext = Context<Type>;
+impl ValueContext {
+    /// The builtins without the prelude
+    pub fn without_prelude() -> Self {
+        let (_, values) = &*BUILTIN_CONTEXTS;
+        values.clone()
+    }
+}
 
+pub type TypeContext = Context<TypeBinding>;
+
+/// The builtins and the prelude
 impl Default for TypeContext {
     fn default() -> Self {
         let (types, _) = &*DEFAULT_CONTEXTS;
-        Context {map: types.clone()}
+        types.clone()
+    }
+}
+
+impl TypeContext {
+    /// The builtins without the prelude
+    pub fn without_prelude() -> Self {
+        let (types, _) = &*BUILTIN_CONTEXTS;
+        types.clone()
     }
 }
 
 lazy_static! {
-    static ref DEFAULT_CONTEXTS: (Map<Name, Type>, Map<Name, Value>) = {
-        let initial_values: Vec<(&'static str, Type, Value)> = vec![
-            ("nil", Type::Nil, Value::Nil),
-            ("Nil", Type::Type, Value::Type(Type::Nil)),
-            ("Type", Type::Type, Value::Type(Type::Type)),
-            ("Number", Type::Type, Value::Type(Type::Number)),
-            ("String", Type::Type, Value::Type(Type::String_)),
-        ];
-
-        let mut types = Map::default();
-        let mut values = Map::default();
-
-        for (name, ty, value) in initial_values {
-            types.insert(name.into(), ty);
-            values.insert(name.into(), value);
-        }
-
-        (types, values)
+    static ref BUILTIN_CONTEXTS: (TypeContext, ValueContext) = {
+        Builtins::standard().add_to(&Context::empty(), &Context::empty())
+    };
+
+    static ref DEFAULT_CONTEXTS: (TypeContext, ValueContext) = {
+        let (types, values) = &*BUILTIN_CONTEXTS;
+        prelude::add_to(types, values)
     };
 }
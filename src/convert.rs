@@ -0,0 +1,481 @@
+//! Moving data between Rust and the language.
+//!
+//! `IntoValue` and `FromValue` are implemented for common Rust types. To convert
+//! a struct to and from a record, declare it with the `record!` macro:
+//!
+//! ```
+//! use lang::convert::{FromValue, IntoValue};
+//!
+//! lang::record! {
+//!     #[derive(Debug, PartialEq)]
+//!     pub struct Person {
+//!         pub name: String,
+//!         pub age: i64,
+//!     }
+//! }
+//!
+//! let value = Person {name: "Ada".into(), age: 36}.into_value();
+//! assert_eq!(value.to_string(), r#"{name="Ada", age=36}"#);
+//! assert_eq!(Person::from_value(value).unwrap(), Person {name: "Ada".into(), age: 36});
+//! ```
+//!
+//! That is equivalent to implementing `Typed`, `IntoValue` and `FromValue` by hand
+//! like this:
+//!
+//! ```
+//! # #[derive(Debug, PartialEq)]
+//! # pub struct Person { pub name: String, pub age: i64 }
+//! use lang::{convert::*, typeck::Type, vm::Value};
+//!
+//! impl Typed for Person {
+//!     fn value_type() -> Type {
+//!         Type::Record(vec![
+//!             ("name".into(), String::value_type()),
+//!             ("age".into(), i64::value_type()),
+//!         ].into_iter().collect(), None)
+//!     }
+//! }
+//!
+//! impl IntoValue for Person {
+//!     fn into_value(self) -> Value {
+//!         Value::Record(vec![
+//!             ("name".into(), self.name.into_value()),
+//!             ("age".into(), self.age.into_value()),
+//!         ].into_iter().collect())
+//!     }
+//! }
+//!
+//! impl FromValue for Person {
+//!     fn from_value(value: Value) -> Result<Self, ConversionError> {
+//!         Ok(Person {
+//!             name: record_field(&value, "name", Self::value_type())?,
+//!             age: record_field(&value, "age", Self::value_type())?,
+//!         })
+//!     }
+//! }
+//!
+//! let value = Person {name: "Ada".into(), age: 36}.into_value();
+//! assert_eq!(Person::from_value(value).unwrap(), Person {name: "Ada".into(), age: 36});
+//! assert!(Person::from_value(Value::Nil).is_err());
+//! ```
+//!
+//! Maps and sets are converted in sorted order, so that a `HashMap` always
+//! becomes the same value:
+//!
+//! ```
+//! use {lang::convert::{FromValue, IntoValue}, std::collections::HashMap};
+//!
+//! let map: HashMap<String, i64> = vec![("b".into(), 2), ("a".into(), 1)].into_iter().collect();
+//! let value = map.clone().into_value();
+//! assert_eq!(value.to_string(), r#"["a": 1, "b": 2]"#);
+//! assert_eq!(HashMap::from_value(value).unwrap(), map);
+//! ```
+
+use {
+    crate::{
+        ast::Name,
+        typeck::Type,
+        vm::Value,
+    },
+    derive_more::Display,
+    num_bigint::BigInt,
+    num_traits::ToPrimitive,
+    std::{
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+        hash::Hash,
+    },
+};
+
+/// A Rust type whose values correspond to values of a particular type in the language
+pub trait Typed {
+    fn value_type() -> Type;
+}
+
+pub trait IntoValue: Typed {
+    fn into_value(self) -> Value;
+}
+
+pub trait FromValue: Typed + Sized {
+    fn from_value(value: Value) -> Result<Self, ConversionError>;
+}
+
+/// A value couldn't be converted to a Rust type, because it wasn't of the expected type
+#[derive(Debug, Display, Clone)]
+#[display(fmt = "expected a value of type {}, found {}", expected, found)]
+pub struct ConversionError {
+    pub expected: Type,
+    pub found: Box<Value>,
+}
+
+impl ConversionError {
+    pub fn new(expected: Type, found: Value) -> Self {
+        Self {expected, found: Box::new(found)}
+    }
+}
+
+/// Converts the field called `name` of a record. `record_type` is used in the
+/// error if `value` isn't a record or doesn't have that field.
+pub fn record_field<T: FromValue>(value: &Value, name: &str, record_type: Type) -> Result<T, ConversionError> {
+    match value {
+        Value::Record(fields) => match fields.get(&Name::from(name)) {
+            Some(field) => T::from_value(field.clone()),
+            None => Err(ConversionError::new(record_type, value.clone())),
+        },
+        _ => Err(ConversionError::new(record_type, value.clone())),
+    }
+}
+
+impl Typed for () {
+    fn value_type() -> Type {
+        Type::Nil
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Nil => Ok(()),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl Typed for BigInt {
+    fn value_type() -> Type {
+        Type::Int
+    }
+}
+
+impl IntoValue for BigInt {
+    fn into_value(self) -> Value {
+        Value::Int(self)
+    }
+}
+
+impl FromValue for BigInt {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Int(int) => Ok(int),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl Typed for i64 {
+    fn value_type() -> Type {
+        Type::Int
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Int(self.into())
+    }
+}
+
+impl FromValue for i64 {
+    /// Fails if the Int is too big to fit in an i64
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Int(ref int) => match int.to_i64() {
+                Some(int) => Ok(int),
+                None => Err(ConversionError::new(Self::value_type(), value)),
+            },
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl Typed for f64 {
+    fn value_type() -> Type {
+        Type::Float
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Float(float) => Ok(float),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl Typed for bool {
+    fn value_type() -> Type {
+        Type::Bool
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl Typed for String {
+    fn value_type() -> Type {
+        Type::String_
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String_(self)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::String_(s) => Ok(s),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl Typed for &str {
+    fn value_type() -> Type {
+        Type::String_
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String_(self.into())
+    }
+}
+
+impl<T: Typed> Typed for Option<T> {
+    fn value_type() -> Type {
+        Type::Optional(Box::new(T::value_type()))
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        Value::Optional(self.map(|value| Box::new(value.into_value())))
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Optional(Some(value)) => T::from_value(*value).map(Some),
+            Value::Optional(None) => Ok(None),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl<T: Typed> Typed for Vec<T> {
+    fn value_type() -> Type {
+        Type::List(Box::new(T::value_type()))
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::List(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::List(list) => list.into_iter().map(T::from_value).collect(),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl<K: Typed, V: Typed> Typed for BTreeMap<K, V> {
+    fn value_type() -> Type {
+        Type::Map(Box::new(K::value_type()), Box::new(V::value_type()))
+    }
+}
+
+impl<K: IntoValue, V: IntoValue> IntoValue for BTreeMap<K, V> {
+    fn into_value(self) -> Value {
+        Value::Map(self.into_iter().map(|(k, v)| (k.into_value(), v.into_value())).collect())
+    }
+}
+
+impl<K: FromValue + Ord, V: FromValue> FromValue for BTreeMap<K, V> {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Map(map) => {
+                map.into_iter().map(|(k, v)| Ok((K::from_value(k)?, V::from_value(v)?))).collect()
+            }
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl<K: Typed, V: Typed> Typed for HashMap<K, V> {
+    fn value_type() -> Type {
+        BTreeMap::<K, V>::value_type()
+    }
+}
+
+impl<K: IntoValue + Ord, V: IntoValue> IntoValue for HashMap<K, V> {
+    /// Sorts the entries by key first, since a HashMap's order changes from run to run
+    fn into_value(self) -> Value {
+        self.into_iter().collect::<BTreeMap<K, V>>().into_value()
+    }
+}
+
+impl<K: FromValue + Eq + Hash, V: FromValue> FromValue for HashMap<K, V> {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Map(map) => {
+                map.into_iter().map(|(k, v)| Ok((K::from_value(k)?, V::from_value(v)?))).collect()
+            }
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl<T: Typed> Typed for BTreeSet<T> {
+    fn value_type() -> Type {
+        Type::Set(Box::new(T::value_type()))
+    }
+}
+
+impl<T: IntoValue> IntoValue for BTreeSet<T> {
+    fn into_value(self) -> Value {
+        Value::Set(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: FromValue + Ord> FromValue for BTreeSet<T> {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Set(set) => set.into_iter().map(T::from_value).collect(),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+impl<T: Typed> Typed for HashSet<T> {
+    fn value_type() -> Type {
+        BTreeSet::<T>::value_type()
+    }
+}
+
+impl<T: IntoValue + Ord> IntoValue for HashSet<T> {
+    /// Sorts the elements first, since a HashSet's order changes from run to run
+    fn into_value(self) -> Value {
+        self.into_iter().collect::<BTreeSet<T>>().into_value()
+    }
+}
+
+impl<T: FromValue + Eq + Hash> FromValue for HashSet<T> {
+    fn from_value(value: Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Set(set) => set.into_iter().map(T::from_value).collect(),
+            found => Err(ConversionError::new(Self::value_type(), found)),
+        }
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($T:ident $index:tt),*) => {
+        impl<$($T: Typed),*> Typed for ($($T,)*) {
+            fn value_type() -> Type {
+                Type::Tuple(vec![$($T::value_type()),*])
+            }
+        }
+
+        impl<$($T: IntoValue),*> IntoValue for ($($T,)*) {
+            fn into_value(self) -> Value {
+                Value::Tuple(vec![$(self.$index.into_value()),*])
+            }
+        }
+
+        impl<$($T: FromValue),*> FromValue for ($($T,)*) {
+            fn from_value(value: Value) -> Result<Self, ConversionError> {
+                match value {
+                    Value::Tuple(ref values) if values.len() == [$($index),*].len() => {
+                        Ok(($($T::from_value(values[$index].clone())?,)*))
+                    }
+                    found => Err(ConversionError::new(Self::value_type(), found)),
+                }
+            }
+        }
+    };
+}
+
+// one-element tuples don't exist in the language, so start at two
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Declares a struct that converts to and from a record with the same fields.
+/// See the module documentation for an example.
+#[macro_export]
+macro_rules! record {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($field_vis:vis $field:ident: $field_type:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $($field_vis $field: $field_type),*
+        }
+
+        impl $crate::convert::Typed for $name {
+            fn value_type() -> $crate::typeck::Type {
+                $crate::typeck::Type::Record(vec![$(
+                    (
+                        stringify!($field).into(),
+                        <$field_type as $crate::convert::Typed>::value_type(),
+                    )
+                ),*].into_iter().collect(), None)
+            }
+        }
+
+        impl $crate::convert::IntoValue for $name {
+            fn into_value(self) -> $crate::vm::Value {
+                $crate::vm::Value::Record(vec![$(
+                    (stringify!($field).into(), $crate::convert::IntoValue::into_value(self.$field))
+                ),*].into_iter().collect())
+            }
+        }
+
+        impl $crate::convert::FromValue for $name {
+            fn from_value(value: $crate::vm::Value) -> Result<Self, $crate::convert::ConversionError> {
+                let record_type = <Self as $crate::convert::Typed>::value_type();
+
+                Ok($name {$(
+                    $field: $crate::convert::record_field(&value, stringify!($field), record_type.clone())?
+                ),*})
+            }
+        }
+    };
+}
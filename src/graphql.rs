@@ -0,0 +1,461 @@
+//! Turning a GraphQL schema into types.
+//!
+//! A `Schema` is read from SDL source, like the contents of a `schema.graphql`
+//! file. `Schema::type_named` gives the type for one of the schema's types, and
+//! `Schema::query_type` gives the type of the data a query returns:
+//!
+//! ```
+//! use lang::graphql::Schema;
+//!
+//! let schema = Schema::parse("
+//!     type Query { user(id: ID!): User }
+//!     type User { id: ID!, name: String!, age: Int }
+//! ").unwrap();
+//!
+//! let ty = schema.query_type("{ user(id: 4) { name, years: age } }").unwrap();
+//! assert_eq!(ty.to_string(), "{user: {name: String, years: Int?}?}");
+//! ```
+//!
+//! Objects, interfaces and input objects become records. Enums become unions of
+//! their values, which are strings like `"ADMIN"`, since that's how they are sent
+//! over the wire, and custom scalars become strings. Types that can be
+//! null are optional, GraphQL lists are lists, and unions are unions. In a query,
+//! each member of a union gets the fields selected in fragments on it, and so
+//! does each object type implementing an interface.
+
+use {
+    crate::{
+        ast::Name,
+        typeck::Type,
+        util::{Map, OrderedMap},
+    },
+    derive_more::Display,
+    graphql_parser::{
+        query::{self, Definition, OperationDefinition, Selection, SelectionSet},
+        schema::{self, TypeDefinition},
+        Pos,
+    },
+    std::{fs, path::Path},
+};
+
+#[derive(Debug, Display, Clone)]
+#[display(fmt = "GraphQL error: {}", _0)]
+pub struct GraphqlError(String);
+
+macro_rules! graphql_error {
+    ($($tt:tt)*) => {
+        return Err(GraphqlError(format!($($tt)*)))
+    };
+}
+
+#[derive(Debug, Clone)]
+pub struct Schema {
+    types: Map<String, TypeDefinition>,
+    query: String,
+    mutation: String,
+    subscription: String,
+}
+
+impl Schema {
+    pub fn parse(source: &str) -> Result<Self, GraphqlError> {
+        let document = schema::parse_schema(source)
+            .map_err(|e| GraphqlError(e.to_string()))?;
+
+        let mut schema = Schema {
+            types: Map::default(),
+            query: "Query".into(),
+            mutation: "Mutation".into(),
+            subscription: "Subscription".into(),
+        };
+
+        for definition in document.definitions {
+            match definition {
+                schema::Definition::SchemaDefinition(definition) => {
+                    if let Some(query) = definition.query {
+                        schema.query = query;
+                    }
+                    if let Some(mutation) = definition.mutation {
+                        schema.mutation = mutation;
+                    }
+                    if let Some(subscription) = definition.subscription {
+                        schema.subscription = subscription;
+                    }
+                }
+                schema::Definition::TypeDefinition(definition) => {
+                    let name = type_definition_name(&definition).to_string();
+                    if schema.types.insert(name.clone(), definition).is_some() {
+                        graphql_error!("type `{}` is defined more than once", name)
+                    }
+                }
+                schema::Definition::TypeExtension(_) => {
+                    graphql_error!("type extensions aren't supported")
+                }
+                schema::Definition::DirectiveDefinition(_) => {}
+            }
+        }
+
+        Ok(schema)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, GraphqlError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|e| GraphqlError(format!("couldn't read {}: {}", path.display(), e)))?;
+        Self::parse(&source)
+    }
+
+    /// The type of values of the schema type called `name`, with every field included
+    pub fn type_named(&self, name: &str) -> Result<Type, GraphqlError> {
+        self.named_type(name, &mut Vec::new())
+    }
+
+    /// The type of the data returned by the query, mutation or subscription in `source`
+    pub fn query_type(&self, source: &str) -> Result<Type, GraphqlError> {
+        let document = query::parse_query(source)
+            .map_err(|e| GraphqlError(e.to_string()))?;
+
+        let mut operations = Vec::new();
+        let mut fragments = Map::default();
+        for definition in &document.definitions {
+            match definition {
+                Definition::Operation(operation) => operations.push(operation),
+                Definition::Fragment(fragment) => {
+                    fragments.insert(fragment.name.as_str(), fragment);
+                }
+            }
+        }
+
+        let (root, selection_set) = match operations.as_slice() {
+            [OperationDefinition::SelectionSet(selection_set)] => (&self.query, selection_set),
+            [OperationDefinition::Query(query)] => (&self.query, &query.selection_set),
+            [OperationDefinition::Mutation(mutation)] => (&self.mutation, &mutation.selection_set),
+            [OperationDefinition::Subscription(subscription)] => {
+                (&self.subscription, &subscription.selection_set)
+            }
+            _ => graphql_error!("expected one operation, found {}", operations.len()),
+        };
+
+        if !self.types.contains_key(root) {
+            graphql_error!("the schema doesn't define a `{}` type", root)
+        }
+
+        let mut fields = OrderedMap::default();
+        SelectionContext { schema: self, fragments: &fragments, spreads: Vec::new() }
+            .add_selections(root, None, selection_set, &mut fields)?;
+        Ok(Type::Record(fields, None))
+    }
+
+    fn definition(&self, name: &str) -> Result<&TypeDefinition, GraphqlError> {
+        match self.types.get(name) {
+            Some(definition) => Ok(definition),
+            None => graphql_error!("unknown type `{}`", name),
+        }
+    }
+
+    fn named_type(&self, name: &str, visiting: &mut Vec<String>) -> Result<Type, GraphqlError> {
+        if let Some(ty) = builtin_scalar(name) {
+            return Ok(ty);
+        }
+
+        if visiting.iter().any(|n| n == name) {
+            graphql_error!(
+                "`{}` contains itself, and recursive types aren't supported; \
+                 select the fields you need with a query instead",
+                name,
+            )
+        }
+
+        visiting.push(name.to_string());
+        let ty = match self.definition(name)? {
+            TypeDefinition::Scalar(_) => Type::String_,
+            TypeDefinition::Enum(enum_) => {
+                Type::union(enum_.values.iter().map(|value| Type::Literal(value.name.clone())))
+            }
+            TypeDefinition::Object(schema::ObjectType { fields, .. })
+            | TypeDefinition::Interface(schema::InterfaceType { fields, .. }) => {
+                let fields = fields.iter()
+                    .map(|field| {
+                        let ty = wrapped_type(&field.field_type, &mut |name| self.named_type(name, visiting))?;
+                        Ok((field.name.as_str().into(), ty))
+                    })
+                    .collect::<Result<_, GraphqlError>>()?;
+                Type::Record(fields, None)
+            }
+            TypeDefinition::InputObject(input) => {
+                let fields = input.fields.iter()
+                    .map(|field| {
+                        let ty = wrapped_type(&field.value_type, &mut |name| self.named_type(name, visiting))?;
+                        Ok((field.name.as_str().into(), ty))
+                    })
+                    .collect::<Result<_, GraphqlError>>()?;
+                Type::Record(fields, None)
+            }
+            TypeDefinition::Union(union) => {
+                let members = union.types.iter()
+                    .map(|member| self.named_type(member, visiting))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Type::union(members)
+            }
+        };
+        visiting.pop();
+
+        Ok(ty)
+    }
+
+    fn fields_of(&self, name: &str) -> Result<&[schema::Field], GraphqlError> {
+        match self.definition(name)? {
+            TypeDefinition::Object(object) => Ok(&object.fields),
+            TypeDefinition::Interface(interface) => Ok(&interface.fields),
+            TypeDefinition::Union(_) => Ok(&[]),
+            _ => graphql_error!("`{}` isn't an object, interface or union type", name),
+        }
+    }
+
+    /// The object types a value of `name` can be, if it's a union, or an
+    /// interface that some object types implement
+    fn possible_types(&self, name: &str) -> Result<Option<Vec<&str>>, GraphqlError> {
+        match self.definition(name)? {
+            TypeDefinition::Union(union) => Ok(Some(union.types.iter().map(String::as_str).collect())),
+            TypeDefinition::Interface(_) => {
+                let mut implementations = self.types.values()
+                    .filter_map(|definition| match definition {
+                        TypeDefinition::Object(object)
+                            if object.implements_interfaces.iter().any(|interface| interface == name) =>
+                        {
+                            Some(object.name.as_str())
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                implementations.sort_unstable();
+                Ok(Some(implementations).filter(|implementations| !implementations.is_empty()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn is_leaf(&self, name: &str) -> Result<bool, GraphqlError> {
+        if builtin_scalar(name).is_some() {
+            return Ok(true);
+        }
+        match self.definition(name)? {
+            TypeDefinition::Scalar(_) | TypeDefinition::Enum(_) => Ok(true),
+            _ => Ok(false),
+        }
+    }
+}
+
+struct SelectionContext<'a> {
+    schema: &'a Schema,
+    fragments: &'a Map<&'a str, &'a query::FragmentDefinition>,
+    /// The fragments being spread, to catch fragments that spread themselves
+    spreads: Vec<&'a str>,
+}
+
+impl<'a> SelectionContext<'a> {
+    /// Adds the fields selected on `parent` to `fields`. When `parent` is a member
+    /// of the union or interface `union`, fragments on its other members are left out.
+    fn add_selections(
+        &mut self,
+        parent: &str,
+        union: Option<&str>,
+        selection_set: &'a SelectionSet,
+        fields: &mut OrderedMap<Name, Type>,
+    ) -> Result<(), GraphqlError> {
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    let key = field.alias.as_ref().unwrap_or(&field.name);
+                    let ty = if field.name == "__typename" {
+                        Type::String_
+                    } else {
+                        let definition = self.schema.fields_of(parent)?.iter()
+                            .find(|f| f.name == field.name);
+                        match definition {
+                            Some(definition) => {
+                                self.field_type(&definition.field_type, &field.selection_set, field.position)?
+                            }
+                            None => graphql_error!(
+                                "type `{}` has no field `{}` at {}", parent, field.name, field.position
+                            ),
+                        }
+                    };
+                    merge_field(fields, key, ty, field.position)?;
+                }
+                Selection::FragmentSpread(spread) => {
+                    let name = spread.fragment_name.as_str();
+                    let fragment = match self.fragments.get(name) {
+                        Some(fragment) => *fragment,
+                        None => graphql_error!("unknown fragment `{}` at {}", name, spread.position),
+                    };
+                    if self.spreads.contains(&name) {
+                        graphql_error!("fragment `{}` spreads itself at {}", name, spread.position)
+                    }
+                    let query::TypeCondition::On(condition) = &fragment.type_condition;
+                    if !self.applies(parent, union, condition, spread.position)? {
+                        continue;
+                    }
+
+                    self.spreads.push(name);
+                    self.add_selections(parent, union, &fragment.selection_set, fields)?;
+                    self.spreads.pop();
+                }
+                Selection::InlineFragment(fragment) => {
+                    if let Some(query::TypeCondition::On(condition)) = &fragment.type_condition {
+                        if !self.applies(parent, union, condition, fragment.position)? {
+                            continue;
+                        }
+                    }
+                    self.add_selections(parent, union, &fragment.selection_set, fields)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a fragment on `condition` applies to `parent`
+    fn applies(
+        &self,
+        parent: &str,
+        union: Option<&str>,
+        condition: &str,
+        position: Pos,
+    ) -> Result<bool, GraphqlError> {
+        if condition == parent || Some(condition) == union {
+            return Ok(true);
+        }
+        if let Some(union) = union {
+            let members = self.schema.possible_types(union)?.unwrap_or_default();
+            if members.contains(&condition) {
+                return Ok(false);
+            }
+        }
+        graphql_error!(
+            "fragments on `{}` inside `{}` aren't supported at {}",
+            condition, union.unwrap_or(parent), position,
+        )
+    }
+
+    fn field_type(
+        &mut self,
+        ty: &schema::Type,
+        selection_set: &'a SelectionSet,
+        position: Pos,
+    ) -> Result<Type, GraphqlError> {
+        wrapped_type(ty, &mut |name| {
+            if self.schema.is_leaf(name)? {
+                if !selection_set.items.is_empty() {
+                    graphql_error!("`{}` has no fields to select at {}", name, position)
+                }
+                self.schema.named_type(name, &mut Vec::new())
+            } else {
+                if selection_set.items.is_empty() {
+                    graphql_error!("fields of `{}` must be selected at {}", name, position)
+                }
+                let schema = self.schema;
+                match schema.possible_types(name)? {
+                    Some(members) => {
+                        let members = members.iter()
+                            .map(|member| {
+                                let mut fields = OrderedMap::default();
+                                self.add_selections(member, Some(name), selection_set, &mut fields)?;
+                                Ok(Type::Record(fields, None))
+                            })
+                            .collect::<Result<Vec<_>, GraphqlError>>()?;
+                        Ok(Type::union(members))
+                    }
+                    None => {
+                        let mut fields = OrderedMap::default();
+                        self.add_selections(name, None, selection_set, &mut fields)?;
+                        Ok(Type::Record(fields, None))
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Unwraps list and non-null types, using `named` for the named type inside them.
+/// Types that aren't marked non-null with a `!` are optional.
+fn wrapped_type(
+    ty: &schema::Type,
+    named: &mut dyn FnMut(&str) -> Result<Type, GraphqlError>,
+) -> Result<Type, GraphqlError> {
+    match ty {
+        schema::Type::NonNullType(inner) => non_null_type(inner, named),
+        ty => Ok(Type::Optional(Box::new(non_null_type(ty, named)?))),
+    }
+}
+
+fn non_null_type(
+    ty: &schema::Type,
+    named: &mut dyn FnMut(&str) -> Result<Type, GraphqlError>,
+) -> Result<Type, GraphqlError> {
+    match ty {
+        schema::Type::NamedType(name) => named(name),
+        schema::Type::ListType(element) => Ok(Type::List(Box::new(wrapped_type(element, named)?))),
+        // the parser doesn't allow `T!!`, so this is just for completeness
+        schema::Type::NonNullType(inner) => non_null_type(inner, named),
+    }
+}
+
+/// Selecting a field more than once is allowed as long as the selections agree
+fn merge_field(
+    fields: &mut OrderedMap<Name, Type>,
+    key: &str,
+    ty: Type,
+    position: Pos,
+) -> Result<(), GraphqlError> {
+    let key = Name::from(key);
+    match fields.get_mut(&key) {
+        None => {
+            fields.insert(key, ty);
+        }
+        Some(existing) => {
+            let before = existing.clone();
+            if !merge_selections(existing, ty.clone(), position)? {
+                graphql_error!("`{}` is selected with types {} and {} at {}", key, before, ty, position)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds the fields `new` selects to `existing`, looking through the lists and
+/// optionals around them. Returns false if the types differ in anything else.
+fn merge_selections(existing: &mut Type, new: Type, position: Pos) -> Result<bool, GraphqlError> {
+    match (existing, new) {
+        (Type::Record(existing, None), Type::Record(new, None)) => {
+            for (name, ty) in new {
+                merge_field(existing, name.as_ref(), ty, position)?;
+            }
+            Ok(true)
+        }
+        (Type::Optional(existing), Type::Optional(new)) | (Type::List(existing), Type::List(new)) => {
+            merge_selections(existing, *new, position)
+        }
+        (existing, new) => Ok(*existing == new),
+    }
+}
+
+fn builtin_scalar(name: &str) -> Option<Type> {
+    match name {
+        "Int" => Some(Type::Int),
+        "Float" => Some(Type::Float),
+        "String" | "ID" => Some(Type::String_),
+        "Boolean" => Some(Type::Bool),
+        _ => None,
+    }
+}
+
+fn type_definition_name(definition: &TypeDefinition) -> &str {
+    match definition {
+        TypeDefinition::Scalar(scalar) => &scalar.name,
+        TypeDefinition::Object(object) => &object.name,
+        TypeDefinition::Interface(interface) => &interface.name,
+        TypeDefinition::Union(union) => &union.name,
+        TypeDefinition::Enum(enum_) => &enum_.name,
+        TypeDefinition::InputObject(input) => &input.name,
+    }
+}
@@ -0,0 +1,1391 @@
+use {
+    crate::{
+        ast::{Expr, Ident, Name, Stmt, StmtKind, ReplLineKind},
+        builtins::Builtins,
+        convert::IntoValue,
+        parser::ReplLineParser,
+        typeck::{Substitution, Type, TypeBinding, TypeContext, TypeError, elaborate, elaborate_stmt, show_type},
+        util::{Map, OrderedMap, join},
+        vm::{Value, ValueContext, VmError, evaluate, evaluate_stmt},
+    },
+    derive_more::{Display, From},
+    std::{
+        fs,
+        io,
+        iter,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    },
+};
+
+/// A session that programs can be run in. Bindings made by one program are
+/// visible to the ones that come after it, like in the REPL.
+#[derive(Clone, Default)]
+pub struct Interpreter {
+    type_context: TypeContext,
+    /// What the type checker has worked out about the type variables in `type_context`
+    substitution: Substitution,
+    value_context: ValueContext,
+    /// The directory that imports are relative to, or the current directory if it's None
+    directory: Option<PathBuf>,
+    /// The files that have been imported, by their canonical paths. This is shared
+    /// with the interpreters that load them, so that each file is only checked
+    /// and run once.
+    modules: Arc<Mutex<Map<PathBuf, Module>>>,
+    /// The files that are being imported, from the first one down to the one
+    /// this interpreter is running, to find import cycles
+    loading: Vec<PathBuf>,
+    /// Whether the interpreter started out with only the builtins. The files
+    /// it imports are run the same way.
+    without_prelude: bool,
+}
+
+/// A file that has been imported. It's type checked the first time it's
+/// imported, but only run once a program that imports it is.
+#[derive(Clone)]
+struct Module {
+    /// The type of the record of the names the file defines
+    ty: Type,
+    /// The record of the names whose values are known without running the
+    /// file, like its types
+    known_value: Value,
+    /// Traits, their methods and their implementations. These apply wherever
+    /// the module is imported, so they're in scope there without its name,
+    /// though the methods are also fields of the module.
+    globals: Vec<(Name, TypeBinding)>,
+    /// The interpreter that checked the file, and what it checked, to run it with
+    interpreter: Interpreter,
+    checked: Arc<Checked>,
+    /// What running the file made, once it has been run
+    exports: Option<Exports>,
+}
+
+impl Module {
+    fn add_to_types(&self, ident: &Ident, type_context: &TypeContext) -> TypeContext {
+        let module = TypeBinding::for_value(self.ty.clone(), &self.known_value);
+        self.globals.iter().fold(type_context.extend(ident.name.clone(), module), |type_context, (name, binding)| {
+            type_context.extend(name.clone(), binding.clone())
+        })
+    }
+}
+
+/// The values that running an imported file made
+#[derive(Clone)]
+struct Exports {
+    /// The record of the names the file defines
+    value: Value,
+    /// The values of its trait methods and implementations. Traits only
+    /// matter to the type checker, so they don't have values.
+    globals: Vec<(Name, Value)>,
+}
+
+impl Exports {
+    fn add_to_values(&self, ident: &Ident, value_context: &ValueContext) -> ValueContext {
+        let module = value_context.extend(ident.name.clone(), self.value.clone());
+        self.globals.iter().fold(module, |value_context, (name, value)| {
+            value_context.extend(name.clone(), value.clone())
+        })
+    }
+}
+
+/// A program that has been type checked
+struct Checked {
+    /// The context after the program's statements
+    type_context: TypeContext,
+    /// What the type checker worked out about the variables in `type_context`
+    substitution: Substitution,
+    /// The elaborated program
+    stmts: Vec<Stmt>,
+    expr: Option<Expr>,
+    ty: Type,
+}
+
+/// The result of evaluating a program
+#[derive(Debug, Display, Clone)]
+#[display(fmt = "{}: {}", value, "show_type(ty)")]
+pub struct Evaluation {
+    pub value: Value,
+    pub ty: Type,
+}
+
+/// A variable that's in scope in an Interpreter
+#[derive(Debug, Display, Clone)]
+#[display(fmt = "{}: {}", value, "show_type(ty)")]
+pub struct Binding {
+    pub value: Value,
+    pub ty: Type,
+}
+
+#[derive(Debug, Display, From)]
+pub enum Error {
+    #[display(fmt = "{}", _0)]
+    Parse(String),
+    #[display(fmt = "{}", r#"join("\n", _0.iter())"#)]
+    Type(Vec<TypeError>),
+    #[display(fmt = "{}", _0)]
+    Runtime(VmError),
+    /// An error in an imported file
+    #[display(fmt = "in {}: {}", "_0.display()", _1)]
+    Module(PathBuf, Box<Error>),
+    #[display(fmt = "can't read {}: {}", "_0.display()", _1)]
+    Io(PathBuf, io::Error),
+    /// Files that import each other, starting and ending with the same one
+    #[display(fmt = "import cycle: {}", r#"join(" -> ", _0.iter().map(|path| path.display()))"#)]
+    ImportCycle(Vec<PathBuf>),
+}
+
+impl Interpreter {
+    /// Creates an interpreter with all of the standard builtins and the prelude in scope
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates an interpreter with the standard builtins in scope, but not the
+    /// functions and types that the prelude defines
+    pub fn without_prelude() -> Self {
+        Interpreter {
+            type_context: TypeContext::without_prelude(),
+            value_context: ValueContext::without_prelude(),
+            without_prelude: true,
+            ..Interpreter::new()
+        }
+    }
+
+    /// Parses, type checks and evaluates `source`, which is a list of statements
+    /// optionally followed by an expression, like a line in the REPL.
+    /// If that succeeds, the bindings made by the statements are kept for later calls.
+    /// Otherwise the interpreter is left as it was, except that assignments to
+    /// mutable variables made before a runtime error aren't undone.
+    pub fn eval_str(&mut self, source: &str) -> Result<Evaluation, Error> {
+        let (stmts, expr) = parse(source)?;
+        self.eval(&stmts, expr.as_ref())
+    }
+
+    fn eval(&mut self, stmts: &[Stmt], expr: Option<&Expr>) -> Result<Evaluation, Error> {
+        let checked = self.check(stmts, expr)?;
+        let (value_context, value) = self.run(&checked)?;
+
+        let Checked {type_context, substitution, ty, ..} = checked;
+        self.type_context = type_context;
+        self.substitution = substitution;
+        self.value_context = value_context;
+
+        Ok(Evaluation {value, ty})
+    }
+
+    /// Parses and type checks `source` without evaluating it, and returns its type
+    pub fn check_str(&self, source: &str) -> Result<Type, Error> {
+        let (stmts, expr) = parse(source)?;
+        Ok(self.check(&stmts, expr.as_ref())?.ty)
+    }
+
+    /// Parses and type checks `source`, and returns it with the coercions that the
+    /// type checker inserted, e.g. where a value is used as a value of a union type
+    pub fn elaborate_str(&self, source: &str) -> Result<(Vec<Stmt>, Option<Expr>), Error> {
+        let (stmts, expr) = parse(source)?;
+        let checked = self.check(&stmts, expr.as_ref())?;
+        Ok((checked.stmts, checked.expr))
+    }
+
+    /// Binds `name` to `value` in later programs. It's up to the caller to make
+    /// sure that `value` has type `ty`.
+    pub fn define_value(&mut self, name: &str, ty: Type, value: Value) {
+        self.type_context = self.type_context.extend(name.into(), TypeBinding::for_value(ty, &value));
+        self.value_context = self.value_context.extend(name.into(), value);
+    }
+
+    /// Binds `name` to a Rust value, converted with `IntoValue`
+    pub fn define(&mut self, name: &str, value: impl IntoValue) {
+        let ty = value_type_of(&value);
+        self.define_value(name, ty, value.into_value());
+    }
+
+    /// Makes a function implemented in Rust available to later programs.
+    /// See `Builtins::register`.
+    pub fn register_builtin(
+        &mut self,
+        name: &str,
+        ty: Type,
+        function: impl Fn(Vec<Value>) -> Result<Value, VmError> + Send + Sync + 'static,
+    ) {
+        let mut builtins = Builtins::new();
+        builtins.register(name, ty, function);
+        self.add_builtins(&builtins);
+    }
+
+    /// Makes imports and GraphQL schema files relative to `directory`, instead of
+    /// the current directory
+    pub fn set_directory(&mut self, directory: Option<&Path>) {
+        self.directory = directory.map(Path::to_path_buf);
+        self.add_builtins(&Builtins::graphql(directory));
+    }
+
+    pub fn add_builtins(&mut self, builtins: &Builtins) {
+        let (type_context, value_context) = builtins.add_to(&self.type_context, &self.value_context);
+        self.type_context = type_context;
+        self.value_context = value_context;
+    }
+
+    /// Looks up a variable that's in scope for the next program
+    pub fn get_binding(&self, name: &str) -> Option<Binding> {
+        let name = name.into();
+        let ty = &self.type_context.lookup(&name)?.ty;
+        let value = self.value_context.lookup(&name)?;
+
+        Some(Binding {
+            value: value.clone().read(),
+            ty: self.substitution.resolve(ty),
+        })
+    }
+
+    /// Type checks a program. The interpreter's own substitution is left alone.
+    fn check(&self, stmts: &[Stmt], expr: Option<&Expr>) -> Result<Checked, Error> {
+        let mut type_context = self.type_context.clone();
+        let mut substitution = self.substitution.clone();
+        let mut elaborated_stmts = Vec::new();
+        for stmt in stmts {
+            type_context = match &stmt.kind {
+                StmtKind::Import(path, ident) => {
+                    elaborated_stmts.push(stmt.clone());
+                    self.load(path)?.1.add_to_types(ident, &type_context)
+                }
+                _ => {
+                    let (stmt, next_context) = elaborate_stmt(stmt, &type_context, &mut substitution)?;
+                    elaborated_stmts.push(stmt);
+                    next_context
+                }
+            };
+        }
+
+        let (expr, ty) = match expr {
+            Some(expr) => {
+                let (expr, ty) = elaborate(expr, &type_context, &mut substitution)?;
+                (Some(expr), substitution.resolve(&ty))
+            }
+            None => (None, Type::Nil),
+        };
+
+        Ok(Checked {type_context, substitution, stmts: elaborated_stmts, expr, ty})
+    }
+
+    /// Evaluates a program that has been type checked, running the files it
+    /// imports if they haven't been run yet, and returns the context after its
+    /// statements and its value
+    fn run(&self, checked: &Checked) -> Result<(ValueContext, Value), Error> {
+        let value_context = checked.stmts.iter().try_fold(self.value_context.clone(), |value_context, stmt| {
+            match &stmt.kind {
+                StmtKind::Import(path, ident) => Ok(self.import(path)?.add_to_values(ident, &value_context)),
+                _ => Ok::<_, Error>(evaluate_stmt(stmt, &value_context)?),
+            }
+        })?;
+
+        let value = match &checked.expr {
+            Some(expr) => evaluate(expr, &value_context)?,
+            None => Value::Nil,
+        };
+
+        Ok((value_context, value))
+    }
+}
+
+impl Interpreter {
+    /// Type checks the file at `path`, relative to this interpreter's directory,
+    /// the first time it's imported, and returns the module it defines along
+    /// with its canonical path. The file isn't run.
+    fn load(&self, path: &str) -> Result<(PathBuf, Module), Error> {
+        let path = self.directory.as_deref().unwrap_or_else(|| Path::new("")).join(path);
+        let path = fs::canonicalize(&path).map_err(|error| Error::Io(path, error))?;
+
+        let cached = self.modules.lock().unwrap().get(&path).cloned();
+        if let Some(module) = cached {
+            return Ok((path, module));
+        }
+
+        if let Some(start) = self.loading.iter().position(|loading| *loading == path) {
+            let cycle = self.loading[start..].iter().cloned().chain(iter::once(path)).collect();
+            return Err(Error::ImportCycle(cycle));
+        }
+
+        let source = fs::read_to_string(&path).map_err(|error| Error::Io(path.clone(), error))?;
+        let start = if self.without_prelude { Interpreter::without_prelude() } else { Interpreter::new() };
+        let mut interpreter = Interpreter {
+            modules: self.modules.clone(),
+            loading: self.loading.iter().cloned().chain(iter::once(path.clone())).collect(),
+            ..start
+        };
+        interpreter.set_directory(path.parent());
+        let (stmts, expr) = parse(&source).map_err(|error| in_module(&path, error))?;
+        let checked = interpreter.check(&stmts, expr.as_ref()).map_err(|error| in_module(&path, error))?;
+
+        let module = interpreter.into_module(checked);
+        self.modules.lock().unwrap().insert(path.clone(), module.clone());
+        Ok((path, module))
+    }
+
+    /// Runs the file at `path` the first time a program that imports it is
+    /// run, and returns what it made
+    fn import(&self, path: &str) -> Result<Exports, Error> {
+        let (path, module) = self.load(path)?;
+        if let Some(exports) = module.exports {
+            return Ok(exports);
+        }
+
+        let (value_context, _) = module.interpreter.run(&module.checked).map_err(|error| in_module(&path, error))?;
+        let exports = Exports {
+            value: Value::Record(module.checked.stmts.iter()
+                .flat_map(defined_names)
+                // a mutable variable is exported with the value it ended up with
+                .filter_map(|name| Some((name.clone(), value_context.lookup(&name)?.clone().read())))
+                .collect()),
+            globals: module.globals.iter()
+                .filter_map(|(name, _)| Some((name.clone(), value_context.lookup(name)?.clone())))
+                .collect(),
+        };
+
+        if let Some(module) = self.modules.lock().unwrap().get_mut(&path) {
+            module.exports = Some(exports.clone());
+        }
+        Ok(exports)
+    }
+
+    /// The module made of the names that a file defines, once this interpreter
+    /// has checked it. Its types are resolved, since they're used with other substitutions.
+    fn into_module(self, checked: Checked) -> Module {
+        let mut types = OrderedMap::default();
+        let mut known_values = OrderedMap::default();
+        for name in checked.stmts.iter().flat_map(defined_names) {
+            if let Some(binding) = checked.type_context.lookup(&name) {
+                types.insert(name.clone(), checked.substitution.resolve(&binding.ty));
+                if let Some(value) = &binding.value {
+                    known_values.insert(name, value.clone());
+                }
+            }
+        }
+
+        // everything else that the file didn't start out with is a trait or an
+        // implementation, and the methods of its traits go along with them
+        let defaults = if self.without_prelude { TypeContext::without_prelude() } else { TypeContext::default() };
+        let methods: Vec<Name> = checked.stmts.iter()
+            .filter(|stmt| matches!(stmt.kind, StmtKind::Trait(..)))
+            .flat_map(defined_names)
+            .collect();
+        let globals = checked.type_context.iter()
+            .filter(|(name, _)| {
+                defaults.lookup(name).is_none() && (!types.contains_key(*name) || methods.contains(name))
+            })
+            .map(|(name, binding)| {
+                (name.clone(), TypeBinding {ty: checked.substitution.resolve(&binding.ty), ..binding.clone()})
+            })
+            .collect();
+
+        Module {
+            ty: Type::Record(types, None),
+            known_value: Value::Record(known_values),
+            globals,
+            interpreter: self,
+            checked: Arc::new(checked),
+            exports: None,
+        }
+    }
+}
+
+/// Says that `error` happened in the file at `path`
+fn in_module(path: &Path, error: Error) -> Error {
+    match error {
+        // a cycle already says which files it goes through
+        error @ Error::ImportCycle(_) => error,
+        error => Error::Module(path.to_path_buf(), Box::new(error)),
+    }
+}
+
+/// The names that a statement binds, which are the fields of a module
+fn defined_names(stmt: &Stmt) -> Vec<Name> {
+    match &stmt.kind {
+        StmtKind::Let(ident, ..)
+        | StmtKind::LetMut(ident, ..)
+        | StmtKind::Type(ident, _)
+        | StmtKind::Import(_, ident) => vec![ident.name.clone()],
+        StmtKind::Trait(_, methods) => methods.iter().map(|(name, _, _)| name.name.clone()).collect(),
+        StmtKind::Impl(..) | StmtKind::Assign(..) => Vec::new(),
+    }
+}
+
+fn value_type_of<T: IntoValue>(_: &T) -> Type {
+    T::value_type()
+}
+
+fn parse(source: &str) -> Result<(Vec<Stmt>, Option<Expr>), Error> {
+    let repl_line = ReplLineParser::new().parse(source).map_err(|err| err.to_string())?;
+
+    match repl_line.kind {
+        ReplLineKind::Block(stmts, expr) => Ok((stmts, expr)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{builtins::function, convert::FromValue, json::from_json},
+        num_bigint::BigInt,
+        std::{
+            collections::{BTreeMap, HashSet},
+            thread,
+        },
+    };
+
+    /// Runs each line in turn, and returns what each one evaluated to or its error
+    fn run(interpreter: &mut Interpreter, lines: &[&str]) -> Vec<String> {
+        lines.iter()
+            .map(|line| match interpreter.eval_str(line) {
+                Ok(evaluation) => evaluation.to_string(),
+                Err(err) => format!("error: {}", err),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failed_statement_leaves_the_session_alone() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &["let x = 1;", r#"let x = "s"; let y = 2; 1 + "a""#, "x", "y"]);
+
+        assert!(results[1].starts_with("error: "), "{}", results[1]);
+        assert_eq!(results[2], "1: Int");
+        assert!(results[3].starts_with("error: Undeclared variable y"), "{}", results[3]);
+    }
+
+    #[test]
+    fn check_str_leaves_the_session_alone() {
+        let interpreter = Interpreter::new();
+
+        assert_eq!(interpreter.check_str("let z = 1; (z, z)").unwrap().to_string(), "type (Int, Int)");
+        assert!(interpreter.get_binding("z").is_none());
+    }
+
+    #[test]
+    fn bindings_can_be_read_from_other_threads() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_str(r#"let greeting = "hi" ++ "!";"#).unwrap();
+
+        let binding = thread::spawn(move || interpreter.get_binding("greeting").unwrap()).join().unwrap();
+        assert_eq!(binding.to_string(), r#""hi!": String"#);
+    }
+
+    #[test]
+    fn records_keep_their_field_order() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            r#"{name = "Ada", age = 36, nothing = nil}"#,
+            r#"let r = {z = 1, a = "a"}; (r, {b = r})"#,
+            "{z: Float, a: String}",
+        ]);
+
+        assert_eq!(results[0], r#"{name="Ada", age=36, nothing=nil}: {name: String, age: Int, nothing: Nil}"#);
+        assert_eq!(results[1], r#"({z=1, a="a"}, {b={z=1, a="a"}}): type ({z: Int, a: String}, {b: {z: Int, a: String}})"#);
+        assert_eq!(results[2], "{z: Float, a: String}: Type");
+    }
+
+    #[test]
+    fn duplicate_record_fields_are_type_errors() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &["{a = 1, b = 2, a = 3}", "{a: Int, a: String}", "{a = 1, b = {a = 2}}"]);
+
+        assert!(results[0].starts_with("error: duplicate record field `a`"), "{}", results[0]);
+        assert!(results[1].starts_with("error: duplicate record type field `a`"), "{}", results[1]);
+        assert_eq!(results[2], "{a=1, b={a=2}}: {a: Int, b: {a: Int}}");
+    }
+
+    #[test]
+    fn duplicate_record_fields_point_at_the_first_one() {
+        let errors = match Interpreter::new().check_str("{a = 1, a = 2}") {
+            Err(Error::Type(errors)) => errors,
+            result => panic!("expected a type error, found {:?}", result),
+        };
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span.to_string(), "8..9");
+        assert_eq!(errors[0].notes[0].0, "`a` first defined here");
+        assert_eq!(errors[0].notes[0].1.to_string(), "1..2");
+    }
+
+    #[test]
+    fn ints_dont_overflow() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "123456789012345678901234567890 * 1000000000000",
+            "-(3 - 10) * -2",
+            "7 / 2",
+            "1 / 0",
+            "to_int(-2.7)",
+        ]);
+
+        assert_eq!(results[0], "123456789012345678901234567890000000000000: Int");
+        assert_eq!(results[1], "-14: Int");
+        assert_eq!(results[2], "3: Int");
+        assert_eq!(results[3], "error: VmError: attempted to divide 1 by zero");
+        assert_eq!(results[4], "-2: Int");
+    }
+
+    #[test]
+    fn floats_are_separate_from_ints() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "(1.5 + 2.25, 2.5e-3, 1e10)",
+            "1.0 / 0.0",
+            "1 + 1.0",
+            "to_float(1.0)",
+            "{let t = ((1, 2), 3); (t.0.1, to_float(t.1))}",
+        ]);
+
+        assert_eq!(results[0], "(3.75, 0.0025, 10000000000.0): type (Float, Float, Float)");
+        assert_eq!(results[1], "inf: Float");
+        assert!(results[2].starts_with("error: cannot apply `+` to Int and Float"), "{}", results[2]);
+        assert!(results[3].starts_with("error: expected Int, found Float"), "{}", results[3]);
+        assert_eq!(results[4], "(2, 3.0): type (Int, Float)");
+    }
+
+    #[test]
+    fn strings_are_concatenated_and_interpolated() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            r#"let name = "wörld"; "hello " ++ "${name}! ${length(name)} ${(1, "x")}""#,
+            r#"let r = {a = 1}; "x${ { let y = r; y } }y ${ "}" ++ "{" }""#,
+            r#""a\u{1F600}b\0c\"d\\e\${x}""#,
+            r#""x" ++ 1"#,
+        ]);
+
+        assert_eq!(results[0], r#""hello wörld! 5 (1, \"x\")": String"#);
+        assert_eq!(results[1], r#""x{a=1}y }{": String"#);
+        assert_eq!(results[2], r#""a😀b\0c\"d\\e\${x}": String"#);
+        assert!(results[3].starts_with("error: cannot apply `++` to String and Int"), "{}", results[3]);
+    }
+
+    #[test]
+    fn bad_escapes_are_errors() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[r#""bad \q""#, r#""bad \u{110000}""#, r#""bad \u1234""#, r#""${ nope }""#]);
+
+        assert!(results[0].starts_with("error: invalid escape sequence `\\q`"), "{}", results[0]);
+        assert!(results[1].starts_with("error: invalid unicode escape `\\u{110000}`"), "{}", results[1]);
+        assert!(results[2].starts_with("error: expected `{` and `}` around the code point"), "{}", results[2]);
+        assert!(results[3].starts_with("error: Undeclared variable nope"), "{}", results[3]);
+    }
+
+    #[test]
+    fn string_builtins_count_characters() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            r#"(length("héllo"), substring("héllo wörld", 1, 4))"#,
+            r#"substring("abc", 2, 5)"#,
+            r#"(contains("haystack", "st"), to_upper("straße"), to_lower("ÀB"))"#,
+        ]);
+
+        assert_eq!(results[0], r#"(5, "éll"): type (Int, String)"#);
+        assert_eq!(results[1], "error: VmError: substring range 2..5 is out of bounds for string of length 3");
+        assert_eq!(results[2], r#"(true, "STRASSE", "àb"): type (Bool, String, String)"#);
+    }
+
+    #[test]
+    fn standard_builtins() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "(abs(-3), min(2, 5), max(2, 5), pow(2, 100))",
+            "(sqrt(2.25), floor(-1.5), ceil(1.2), round(2.5))",
+            r#"(to_string(1), to_string("a"), to_string({a = 1.5}))"#,
+            "min(1, 2.0)",
+            "pow(2, -1)",
+        ]);
+
+        assert_eq!(results[0], "(3, 2, 5, 1267650600228229401496703205376): type (Int, Int, Int, Int)");
+        assert_eq!(results[1], "(1.5, -2.0, 2.0, 3.0): type (Float, Float, Float, Float)");
+        assert_eq!(results[2], r#"("1", "a", "{a=1.5}"): type (String, String, String)"#);
+        assert!(results[3].starts_with("error: expected Int, found Float"), "{}", results[3]);
+        assert!(results[4].starts_with("error: VmError: "), "{}", results[4]);
+    }
+
+    #[test]
+    fn registered_builtins_are_checked_and_called() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_value("answer", Type::Int, Value::Int(42.into()));
+        interpreter.register_builtin("twice", function(vec![Type::String_], Type::String_), |args| match &args[..] {
+            [Value::String_(s)] => Ok(Value::String_(s.repeat(2))),
+            _ => unreachable!(),
+        });
+        let results = run(&mut interpreter, &[r#"(twice("ab"), answer, abs(-1))"#, "twice(answer)"]);
+
+        assert_eq!(results[0], r#"("abab", 42, 1): type (String, Int, Int)"#);
+        assert!(results[1].starts_with("error: expected String, found Int"), "{}", results[1]);
+    }
+
+    #[test]
+    fn polymorphic_builtins_are_instantiated_for_each_use() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &["(to_string(1), to_string(true))", "to_string(1 + true)"]);
+
+        assert_eq!(results[0], r#"("1", "true"): type (String, String)"#);
+        assert!(results[1].starts_with("error: cannot apply `+` to Int and Bool"), "{}", results[1]);
+    }
+
+    #[test]
+    fn rust_values_can_be_defined_and_read_back() {
+        crate::record! {
+            #[derive(Debug, PartialEq)]
+            struct Point {
+                x: i64,
+                y: f64,
+            }
+        }
+
+        let mut interpreter = Interpreter::new();
+        interpreter.define("origin", Point {x: 0, y: 0.5});
+        interpreter.define("pair", (1i64, "one"));
+        let results = run(&mut interpreter, &["let p = {x = origin.x + 2, y = origin.y}; (p, pair.1)", "origin.z"]);
+
+        assert_eq!(results[0], r#"({x=2, y=0.5}, "one"): type ({x: Int, y: Float}, String)"#);
+        assert!(results[1].starts_with("error: "), "{}", results[1]);
+
+        let p = Point::from_value(interpreter.get_binding("p").unwrap().value).unwrap();
+        assert_eq!(p, Point {x: 2, y: 0.5});
+    }
+
+    #[test]
+    fn values_of_the_wrong_shape_arent_converted() {
+        let error = <(i64, String)>::from_value((1i64, 2i64).into_value()).unwrap_err();
+        assert_eq!(error.to_string(), "expected a value of type String, found 2");
+
+        let big = BigInt::from(i64::MAX) + BigInt::from(1);
+        assert!(i64::from_value(big.clone().into_value()).is_err());
+        assert_eq!(BigInt::from_value(big.clone().into_value()).unwrap(), big);
+    }
+
+    #[test]
+    fn json_numbers_keep_their_digits() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "to_json((123456789012345678901234567890, -1))",
+            "to_json({nan = 0.0 / 0.0})",
+            "to_json(-1.0 / 0.0)",
+        ]);
+
+        assert_eq!(results[0], r#""[123456789012345678901234567890,-1]": String"#);
+        assert!(results[1].ends_with("can't serialize NaN, which isn't a finite number"), "{}", results[1]);
+        assert!(results[2].ends_with("can't serialize -inf, which isn't a finite number"), "{}", results[2]);
+
+        let big = from_json("123456789012345678901234567890", &Type::Int).unwrap();
+        assert_eq!(big.to_string(), "123456789012345678901234567890");
+        let error = from_json("1.5", &Type::Int).unwrap_err();
+        assert_eq!(error.to_string(), "expected Int, found fractional Number 1.5");
+
+        let value: Value = serde_json::from_str(r#"{"big": 123456789012345678901234567890, "half": 0.5}"#).unwrap();
+        assert_eq!(value.to_string(), "{big=123456789012345678901234567890, half=0.5}");
+    }
+
+    #[test]
+    fn json_errors_say_where_the_document_is_wrong() {
+        let point = Type::Record(vec![
+            ("x".to_string().into(), Type::Int),
+            ("y".to_string().into(), Type::Int),
+        ].into_iter().collect(), None);
+        let line = Type::Tuple(vec![point.clone(), point]);
+
+        let value = from_json(r#"[{"x": 1, "y": 2}, {"y": 4, "x": 3}]"#, &line).unwrap();
+        assert_eq!(value.to_string(), "({x=1, y=2}, {x=3, y=4})");
+
+        let errors = [
+            r#"[{"x": 1, "y": 2}, {"x": 3, "y": "4"}]"#,
+            r#"[{"x": 1, "y": 2}, {"x": 3}]"#,
+            r#"[{"x": 1, "y": 2}]"#,
+            r#"[{"x": 1, "y": 2}, {"x": 3, "y": 4}"#,
+        ];
+        let errors: Vec<_> = errors.iter().map(|text| from_json(text, &line).unwrap_err().to_string()).collect();
+        assert_eq!(errors[0], "[1].y: expected Int, found String");
+        assert_eq!(errors[1], "[1]: missing field `y`");
+        assert_eq!(errors[2], "expected an array of 2 elements, found 1");
+        assert!(errors[3].starts_with("EOF while parsing"), "{}", errors[3]);
+    }
+
+    #[test]
+    fn graphql_queries_are_typed_against_a_schema() {
+        let path = std::env::temp_dir().join(format!("lang-graphql-{}.graphql", std::process::id()));
+        std::fs::write(&path, "
+            type Query { user(id: ID!): User }
+            type User { id: ID!, name: String!, age: Int, best_friend: User, friends: [User!]! }
+        ").unwrap();
+
+        let path = path.to_str().unwrap();
+        let query = |query: &str| format!("graphql_query({:?}, {:?})", path, query);
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            &format!("graphql_type({:?}, \"User\")", path),
+            &query("{ user(id: 1) { name } user(id: 1) { age } }"),
+            &query("{ user(id: 1) { best_friend { name } best_friend { id } } }"),
+            &query("{ ...names } fragment names on Query { user(id: 1) { years: age } }"),
+            &query("{ user(id: 1) { friends { name } friends { id } } }"),
+            &query("{ user(id: 1) { name } user(id: 1) { name: age } }"),
+            &query("{ user(id: 1) { email } }"),
+            &query("{ user(id: 1) }"),
+        ]);
+
+        assert!(results[0].contains("`User` contains itself"), "{}", results[0]);
+        assert_eq!(results[1], "{user: {name: String, age: Int?}?}: Type");
+        assert_eq!(results[2], "{user: {best_friend: {name: String, id: String}?}?}: Type");
+        assert_eq!(results[3], "{user: {years: Int?}?}: Type");
+        assert_eq!(results[4], "{user: {friends: List {name: String, id: String}}?}: Type");
+        assert!(results[5].contains("`name` is selected with types String and Int?"), "{}", results[5]);
+        assert!(results[6].contains("type `User` has no field `email`"), "{}", results[6]);
+        assert!(results[7].contains("fields of `User` must be selected"), "{}", results[7]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn optionals_have_to_be_unwrapped_before_use() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let user = some({name=\"Ada\", nickname=none});",
+            "(user?.name, user?.nickname ?? \"Countess\")",
+            "if let u = user { u.name } else { \"nobody\" }",
+            "user.name",
+            "(none ?? 4, some((1, 2))?.1)",
+        ]);
+
+        assert_eq!(results[1], r#"(some("Ada"), "Countess"): type (String?, String)"#);
+        assert_eq!(results[2], r#""Ada": String"#);
+        assert!(results[3].starts_with("error: "), "{}", results[3]);
+        assert!(results[3].contains("?."), "{}", results[3]);
+        assert_eq!(results[4], "(4, some(2)): type (Int, Int?)");
+    }
+
+    #[test]
+    fn list_builtins() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let xs = [3, 1, 2];",
+            "(len(xs), get(xs, 0), get(xs, 3), push(xs, 4), concat(xs, [5]))",
+            "(map(xs, |x| x * 2), filter(xs, |x| contains(\"23\", to_string(x))), fold(xs, \"\", |acc, x| acc ++ to_string(x)))",
+            "xs",
+            "split(\"a,b,,c\", \",\")",
+            "[1, \"two\"]",
+            "map(xs, |x| x ++ \"!\")",
+        ]);
+
+        assert_eq!(results[1], "(3, some(3), none, [3, 1, 2, 4], [3, 1, 2, 5]): type (Int, Int?, Int?, List Int, List Int)");
+        assert_eq!(results[2], r#"([6, 2, 4], [3, 2], "312"): type (List Int, List Int, String)"#);
+        assert_eq!(results[3], "[3, 1, 2]: List Int");
+        assert_eq!(results[4], r#"["a", "b", "", "c"]: List String"#);
+        assert!(results[5].starts_with("error: "), "{}", results[5]);
+        assert!(results[6].starts_with("error: "), "{}", results[6]);
+    }
+
+    #[test]
+    fn literal_map_keys_are_checked_for_duplicates() {
+        let interpreter = Interpreter::new();
+
+        let error = interpreter.check_str(r#"[1: "a", 1: "b"]"#).unwrap_err().to_string();
+        assert!(error.starts_with("duplicate map key `1`"), "{}", error);
+        let error = interpreter.check_str(r#"[-1: "a", (-1): "b"]"#).unwrap_err().to_string();
+        assert!(error.starts_with("duplicate map key `-1`"), "{}", error);
+        let error = interpreter.check_str(r#"["x": 1, "y": 2, "x": 3]"#).unwrap_err().to_string();
+        assert!(error.starts_with(r#"duplicate map key `"x"`"#), "{}", error);
+        assert_eq!(interpreter.check_str(r#"["1": 1, "2": 2]"#).unwrap().to_string(), "Map String Int");
+    }
+
+    #[test]
+    fn map_and_set_builtins() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            r#"let ages = ["ann": 31, "bob": 27];"#,
+            r#"(lookup(ages, "bob"), lookup(ages, "cy"), has_key(ages, "ann"), remove(ages, "ann"))"#,
+            r#"(keys(insert(ages, "cy", 40)), values(ages), entries(ages), merge(ages, ["ann": 32]))"#,
+            "let s = #[3, 1, 2];",
+            "(member(s, 2), add(s, 0), discard(s, 3), union(s, #[4]), intersection(s, #[1, 5]), difference(s, #[1]))",
+            "(elements(s), to_set([2, 2, 1]))",
+            "[1: \"a\", 1 + 0: \"b\"]",
+            "to_json([(1, 2): 1])",
+        ]);
+
+        assert_eq!(results[1], r#"(some(27), none, true, ["bob": 27]): type (Int?, Int?, Bool, Map String Int)"#);
+        assert_eq!(
+            results[2],
+            r#"(["ann", "bob", "cy"], [31, 27], [("ann", 31), ("bob", 27)], ["ann": 32, "bob": 27]): type (List String, List Int, List type (String, Int), Map String Int)"#,
+        );
+        assert_eq!(
+            results[4],
+            "(true, #[3, 1, 2, 0], #[1, 2], #[3, 1, 2, 4], #[1], #[3, 2]): type (Bool, Set Int, Set Int, Set Int, Set Int, Set Int)",
+        );
+        assert_eq!(results[5], "([3, 1, 2], #[2, 1]): type (List Int, Set Int)");
+        assert_eq!(results[6], "error: VmError: duplicate map key 1");
+        assert_eq!(results[7], "error: VmError: to_json: key must be a string");
+    }
+
+    #[test]
+    fn json_maps_with_non_string_keys_round_trip() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &["to_json([1: \"a\", 20: \"b\"])", "to_json([true: 1.5])"]);
+        assert_eq!(results[0], r#""{\"1\":\"a\",\"20\":\"b\"}": String"#);
+        assert_eq!(results[1], r#""{\"true\":1.5}": String"#);
+
+        let map = Type::Map(Box::new(Type::Int), Box::new(Type::String_));
+        let value = from_json(r#"{"1": "a", "20": "b"}"#, &map).unwrap();
+        assert_eq!(value.to_string(), r#"[1: "a", 20: "b"]"#);
+        let error = from_json(r#"{"1": "a", "x": "b"}"#, &map).unwrap_err();
+        assert_eq!(error.to_string(), r#"["x"]: expected Int, found String"#);
+
+        let ages: BTreeMap<String, i64> = vec![("bob".into(), 27), ("ann".into(), 31)].into_iter().collect();
+        let value = ages.clone().into_value();
+        assert_eq!(value.to_string(), r#"["ann": 31, "bob": 27]"#);
+        assert_eq!(BTreeMap::from_value(value).unwrap(), ages);
+        let set: HashSet<i64> = vec![3, 1, 2].into_iter().collect();
+        assert_eq!(set.into_value().to_string(), "#[1, 2, 3]");
+    }
+
+    #[test]
+    fn let_bound_functions_are_polymorphic() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let id = |x| x;",
+            r#"(id(1), id("a"))"#,
+            "id",
+            "|f| (f(1), f(\"a\"))",
+            "|x| x(x)",
+        ]);
+
+        assert_eq!(results[1], r#"(1, "a"): type (Int, String)"#);
+        assert_eq!(results[2], "<function |x|>: forall a. (a) -> a");
+        assert!(results[3].starts_with("error: expected Int, found String"), "{}", results[3]);
+        assert!(results[4].starts_with("error: infinite type: a would have to contain itself in (a) -> b"), "{}", results[4]);
+    }
+
+    #[test]
+    fn elements_of_tuples_that_arent_known_yet() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let fst = |p| p.0; fst",
+            r#"fst((1, "a"))"#,
+            "let snd = |p| p.1; snd((1, true, nil))",
+            "snd(1)",
+        ]);
+
+        assert_eq!(results[0], "<function |p|>: forall a b. (type (a, ..b)) -> a");
+        assert_eq!(results[1], "1: Int");
+        assert_eq!(results[2], "true: Bool");
+        assert!(results[3].starts_with("error: expected type (a, b, ..c), found Int"), "{}", results[3]);
+    }
+
+    #[test]
+    fn conflicts_point_at_where_types_were_inferred() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_str("let f = |x| { let y = x; y + 1 };").unwrap();
+
+        for line in &[r#"f("s")"#, r#"|g| (g(1), g("a"))"#] {
+            match interpreter.eval_str(line) {
+                Err(Error::Type(errors)) => {
+                    assert_eq!(errors[0].message, "expected Int, found String");
+                    let notes: Vec<&str> = errors[0].notes.iter().map(|(note, _)| note.as_str()).collect();
+                    assert_eq!(notes, ["inferred to be Int here"]);
+                }
+                result => panic!("expected a type error, found {:?}", result.map(|evaluation| evaluation.to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn expected_types_are_pushed_into_expressions() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            r#"{let people = [{name="Ada", age=36}]; map(people, |p| p.name)}"#,
+            r#"fold([1, 2], "", |acc, x| acc ++ to_string(x))"#,
+            r#"[{name="Ada", age=36}, {name="Alan", age="41"}]"#,
+            r#"merge(["a": 1], ["b": 2, "b": 3])"#,
+        ]);
+
+        assert_eq!(results[0], r#"["Ada"]: List String"#);
+        assert_eq!(results[1], r#""12": String"#);
+        assert_eq!(results[2], "error: expected Int, found String at 41..45");
+        assert!(results[3].starts_with(r#"error: duplicate map key `"b"` at 25..28"#), "{}", results[3]);
+    }
+
+    #[test]
+    fn function_types_can_be_written() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &["(Int, String) -> Bool", "() -> List Int", "(Int) -> 2"]);
+
+        assert_eq!(results[0], "(Int, String) -> Bool: Type");
+        assert_eq!(results[1], "() -> List Int: Type");
+        assert!(results[2].starts_with("error: expected type of function return to be a type"), "{}", results[2]);
+    }
+
+    #[test]
+    fn extended_records_keep_their_field_order() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            r#"{{b = 1, a = 2} with c = 3, a = "x"}"#,
+            "let extend = |r| {r with z = 1}; extend({y = 2, x = 3})",
+            r#"{extend({y = 2, x = 3}) with y = "s"}"#,
+        ]);
+
+        assert_eq!(results[0], r#"{b=1, a="x", c=3}: {b: Int, a: String, c: Int}"#);
+        assert_eq!(results[1], "{y=2, x=3, z=1}: {y: Int, x: Int, z: Int}");
+        assert_eq!(results[2], r#"{y="s", x=3, z=1}: {y: String, x: Int, z: Int}"#);
+    }
+
+    #[test]
+    fn functions_take_any_record_with_the_fields_they_need() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let birthday = |p| {p with age = p.age + 1};",
+            r#"birthday({name = "Ada", age = 36})"#,
+            "birthday({id = 7, age = 1})",
+            r#"birthday({name = "Ada"})"#,
+            r#"{{name = "Ada", age = 36, password = "hunter2"} without password}"#,
+            "{{a = 1} without b}",
+        ]);
+
+        assert_eq!(results[1], r#"{name="Ada", age=37}: {name: String, age: Int}"#);
+        assert_eq!(results[2], "{id=7, age=2}: {id: Int, age: Int}");
+        assert!(results[3].starts_with("error:"), "{}", results[3]);
+        assert_eq!(results[4], r#"{name="Ada", age=36}: {name: String, age: Int}"#);
+        assert!(results[5].starts_with("error:"), "{}", results[5]);
+    }
+
+    #[test]
+    fn later_fields_can_depend_on_earlier_ones() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "{T: Type, value: T, others: List T}",
+            "type (T: Type, T, List T)",
+            "{value: T, T: Type}",
+            "{T: Int, value: T}",
+        ]);
+
+        assert_eq!(results[0], "{T: Type, value: .T, others: List .T}: Type 1");
+        assert_eq!(results[1], "type (Type, .0, List .0): Type 1");
+        assert!(results[2].starts_with("error: Undeclared variable T"), "{}", results[2]);
+        assert!(results[3].starts_with("error: expected type of record field to be a type"), "{}", results[3]);
+
+        let boxed = match interpreter.eval_str("{T: Type, value: T}").unwrap().value {
+            Value::Type(ty) => ty,
+            value => panic!("expected a type, found {}", value),
+        };
+        let value = from_json(r#"{"T": "Int", "value": 3}"#, &boxed).unwrap();
+        assert_eq!(value.to_string(), "{T=Int, value=3}");
+        let error = from_json(r#"{"T": "Int", "value": "3"}"#, &boxed).unwrap_err();
+        assert!(error.to_string().starts_with(".value: expected Int"), "{}", error);
+    }
+
+    #[test]
+    fn lambdas_can_be_annotated_with_function_types() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let f: (Int) -> Int = |x| x + 1; f(2)",
+            "f",
+            "let g: () -> String = || \"hi\"; g()",
+            "let bad: (Int) -> Int = |x| \"s\";",
+        ]);
+
+        assert_eq!(results[0], "3: Int");
+        assert_eq!(results[1], "<function |x|>: (Int) -> Int");
+        assert_eq!(results[2], r#""hi": String"#);
+        assert!(results[3].starts_with("error: expected Int, found String"), "{}", results[3]);
+    }
+
+    #[test]
+    fn annotations_are_types_worked_out_before_the_program_runs() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let Point = type (Int, Int);",
+            "let origin: Point = (0, 0); origin",
+            r#"let other: Point = (0, "0");"#,
+            r#"from_json("{\"name\": \"Ada\", \"tags\": [\"admin\"]}", {name: String, tags: List String}).tags"#,
+            r#"from_json("[1, \"x\"]", List Int)"#,
+            "let n: 1 = 2;",
+            "let Pair = |T| type (T, T); let p: Pair(Int) = (1, 2);",
+        ]);
+
+        assert_eq!(results[1], "(0, 0): type (Int, Int)");
+        assert!(results[2].starts_with("error: expected Int, found String"), "{}", results[2]);
+        assert_eq!(results[3], r#"["admin"]: List String"#);
+        assert_eq!(results[4], "error: VmError: from_json: [1]: expected Int, found String");
+        assert!(results[5].starts_with("error: expected type of annotation to be a type"), "{}", results[5]);
+        assert!(results[6].starts_with("error: can't work out which type this is before the program runs"), "{}", results[6]);
+    }
+
+    #[test]
+    fn types_of_types_are_in_higher_universes() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "Int",
+            "Type",
+            "Type 1",
+            "List Type",
+            "(Type) -> Int",
+            "{T: Type 1, x: T}",
+            "let xs = [Int, Type];",
+            "Option(Type)",
+            "{let T: Type 1 = Int; T}",
+            "let U: Type = Type;",
+        ]);
+
+        assert_eq!(results[..6], [
+            "Int: Type",
+            "Type: Type 1",
+            "Type 1: Type 2",
+            "List Type: Type 1",
+            "(Type) -> Int: Type 1",
+            "{T: Type 1, x: .T}: Type 2",
+        ]);
+        assert!(results[6].starts_with("error: expected a type in Type, found one in Type 1"), "{}", results[6]);
+        assert!(results[7].starts_with("error: expected a type in Type, found one in Type 1"), "{}", results[7]);
+        assert_eq!(results[8], "Int: Type 1");
+        assert!(results[9].starts_with("error: expected a type in Type, found one in Type 1"), "{}", results[9]);
+    }
+
+    #[test]
+    fn declared_types_are_named_in_results_and_can_be_recursive() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "type Point = type (Int, Int);",
+            "let p: Point = (1, 2); p",
+            "p.0",
+            "type Tree = {value: Int, children: List Tree};",
+            "let leaf: Tree = {value = 1, children = []};",
+            "let tree: Tree = {value = 2, children = [leaf]}; tree.children",
+            r#"let bad: Tree = {value = 3, children = [{value = "x", children = []}]};"#,
+            r#"from_json("{\"value\": 1, \"children\": [{\"value\": 2, \"children\": []}]}", Tree).children"#,
+            "type Loop = Loop;",
+        ]);
+
+        assert_eq!(results[1], "(1, 2): Point");
+        assert_eq!(results[2], "1: Int");
+        assert_eq!(results[5], "[{value=1, children=[]}]: List Tree");
+        assert!(results[6].starts_with("error: expected Int, found String"), "{}", results[6]);
+        assert_eq!(results[7], "[{value=2, children=[]}]: List Tree");
+        assert!(results[8].starts_with("error: type Loop can't be defined as itself"), "{}", results[8]);
+    }
+
+    #[test]
+    fn subtyping_looks_inside_types() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "type IntList = Nil | type (Int, IntList); let l: IntList = (1, (2, nil)); l",
+            "let v: Int | {a: Int | String} = {a = 1}; v",
+            "let xs = [1]; let ys: List (Int | String) = xs; ys",
+            "let o: (Int | String)? = some(1); o",
+            r#"let bad: IntList = (1, ("a", nil));"#,
+        ]);
+
+        assert_eq!(results[0], "(1, (2, nil)): IntList");
+        assert_eq!(results[1], "{a=1}: Int | {a: Int | String}");
+        assert_eq!(results[2], "[1]: List (Int | String)");
+        assert_eq!(results[3], "some(1): (Int | String)?");
+        assert!(results[4].starts_with("error: expected IntList"), "{}", results[4]);
+    }
+
+    #[test]
+    fn unions_are_narrowed_with_is() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let x: Int | String = 1;",
+            "if x is Int { x + 1 } else { length(x) }",
+            "x is String",
+            "x + 1",
+            r#"let y: Int | String = "abc"; if y is Int { y } else { length(y) }"#,
+            "let z: Int | String = 1.5;",
+        ]);
+
+        assert_eq!(results[1], "2: Int");
+        assert_eq!(results[2], "false: Bool");
+        assert!(results[3].starts_with("error: cannot apply `+` to Int | String and Int"), "{}", results[3]);
+        assert_eq!(results[4], "3: Int");
+        assert!(results[5].starts_with("error: expected Int | String, found Float"), "{}", results[5]);
+    }
+
+    #[test]
+    fn graphql_unions_interfaces_and_enums_become_unions() {
+        let path = std::env::temp_dir().join(format!("lang-graphql-unions-{}.graphql", std::process::id()));
+        std::fs::write(&path, "
+            type Query { node: Node, result: SearchResult, role: Role! }
+            interface Node { id: ID! }
+            type User implements Node { id: ID!, name: String! }
+            type Post implements Node { id: ID!, title: String! }
+            union SearchResult = User | Post
+            enum Role { ADMIN, USER }
+        ").unwrap();
+
+        let path = path.to_str().unwrap();
+        let query = |query: &str| format!("graphql_query({:?}, {:?})", path, query);
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            &query("{ node { id ... on User { name } ... on Post { title } } }"),
+            &query("{ node { id } }"),
+            &query("{ result { ... on User { name } ... on Post { title } } }"),
+            &query("{ result { ... on Query { role } } }"),
+            &format!("let Role = graphql_type({:?}, \"Role\");", path),
+            r#"let role: Role = "ADMIN"; role"#,
+            r#"let other: Role = "OWNER";"#,
+            r#"from_json("\"OWNER\"", Role)"#,
+        ]);
+
+        assert_eq!(results[0], "{node: ({id: String, title: String} | {id: String, name: String})?}: Type");
+        assert_eq!(results[1], "{node: {id: String}?}: Type");
+        assert_eq!(results[2], "{result: ({name: String} | {title: String})?}: Type");
+        assert!(results[3].contains("fragments on `Query` inside `SearchResult` aren't supported"), "{}", results[3]);
+        assert_eq!(results[5], r#""ADMIN": "ADMIN" | "USER""#);
+        assert!(results[6].starts_with(r#"error: expected "ADMIN" | "USER", found String"#), "{}", results[6]);
+        assert_eq!(results[7], r#"error: VmError: from_json: expected "ADMIN" | "USER", found String"#);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn coercions_check_the_value() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_value("wrong", Type::Int, Value::String_("not an int".into()));
+
+        match interpreter.eval_str("let x: Int | Bool = wrong; x") {
+            Err(Error::Runtime(error)) => assert_eq!(error.to_string(), r#"VmError: expected a value of type Int | Bool, found "not an int""#),
+            result => panic!("expected a runtime error, found {:?}", result.map(|evaluation| evaluation.to_string())),
+        }
+    }
+
+    #[test]
+    fn values_used_as_unions_are_coerced() {
+        let interpreter = Interpreter::new();
+        let elaborate = |source: &str| {
+            let (stmts, expr) = interpreter.elaborate_str(source).unwrap();
+            let parts: Vec<String> = stmts.iter().map(ToString::to_string)
+                .chain(expr.iter().map(ToString::to_string))
+                .collect();
+            parts.join("; ")
+        };
+
+        assert_eq!(elaborate("let x: Int | String = 1; x"), "let x: Int | String = (1 as Int | String); x");
+        assert_eq!(
+            elaborate("let y: List (Int | String) = [1, 2]; y"),
+            "let y: List (Int | String) = [(1 as Int | String), (2 as Int | String)]; y",
+        );
+        assert_eq!(
+            elaborate("let z: Int | String = 1; let w: Int | String = z; 1 + 2"),
+            "let z: Int | String = (1 as Int | String); let w: Int | String = z; 1 + 2",
+        );
+    }
+
+    #[test]
+    fn only_functions_take_dictionaries() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "(1, to_string)",
+            "let t = (1, to_string); t",
+            "[to_string]",
+            "t.1(2.5)",
+            "|x, y| to_string(x + y)",
+            "trait Zero { zero: () -> Self }; let z = (1, zero()); z",
+        ]);
+
+        assert_eq!(results[0], "(1, <builtin to_string>): forall a. type (Int, (a) -> String)");
+        assert_eq!(results[1], "(1, <builtin to_string>): forall a. type (Int, (a) -> String)");
+        assert_eq!(results[2], "[<builtin to_string>]: forall a. List (a) -> String");
+        assert_eq!(results[3], r#""2.5": String"#);
+        assert_eq!(results[4], "<function |x, y|>: forall a. (Show a, Add a) => (a, a) -> String");
+        assert!(results[5].starts_with("error: can't work out which implementation of Zero is needed for a"), "{}", results[5]);
+    }
+
+    #[test]
+    fn values_are_shown_and_compared_with_their_implementations() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "type Point = type (Int, Int); impl Show for Point { to_string = |p| \"<${p.0}, ${p.1}>\" };",
+            "let p: Point = (1, 2); (to_string(p), \"p is ${p}\")",
+            "let twice = |x| \"${x}${x}\"; twice(p)",
+            "(equals([1], [1]), equals({a = 1, b = (2, \"c\")}, {b = (2, \"c\"), a = 1}), equals(some(1), none))",
+        ]);
+
+        assert_eq!(results[1], r#"("<1, 2>", "p is <1, 2>"): type (String, String)"#);
+        assert_eq!(results[2], r#""<1, 2><1, 2>": String"#);
+        assert_eq!(results[3], "(true, true, false): type (Bool, Bool, Bool)");
+    }
+
+    #[test]
+    fn functions_are_passed_the_implementations_they_need() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "trait Zero { zero: () -> Self };",
+            "impl Zero for Int { zero = || 0 }; impl Zero for String { zero = || \"\" };",
+            "let sum = |xs| fold(xs, zero(), |total, x| total + x);",
+            "(sum([1, 2, 3]), sum([\"a\", \"b\"]))",
+            "sum",
+            "sum([true])",
+            "impl Zero for Bool { zero = || 0 };",
+        ]);
+
+        assert_eq!(results[3], r#"(6, "ab"): type (Int, String)"#);
+        assert_eq!(results[4], "<function |xs|>: forall a. (Zero a, Add a) => (List a) -> a");
+        assert!(results[5].starts_with("error: no implementation of Zero for Bool"), "{}", results[5]);
+        assert!(results[6].starts_with("error: expected Bool, found Int"), "{}", results[6]);
+    }
+
+    #[test]
+    fn checking_an_import_doesnt_run_it() {
+        let directory = std::env::temp_dir().join(format!("lang-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("broken.lang"), "type T = Int; let n: T = 1 / 0;").unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_directory(Some(&directory));
+        let source = r#"import "broken.lang" as B; let m: B.T = B.n; m"#;
+
+        assert_eq!(interpreter.check_str(source).unwrap().to_string(), "T");
+        assert!(interpreter.elaborate_str(source).is_ok());
+        match interpreter.eval_str(source) {
+            Err(Error::Module(_, error)) => assert!(matches!(*error, Error::Runtime(_)), "{}", error),
+            result => panic!("expected an error running the module, found {:?}", result.map(|evaluation| evaluation.to_string())),
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn modules_are_records_of_the_names_a_file_defines() {
+        let directory = std::env::temp_dir().join(format!("lang-modules-{}", std::process::id()));
+        fs::create_dir_all(directory.join("shapes")).unwrap();
+        fs::write(directory.join("shapes/geometry.lang"), r#"
+            type Point = type (Int, Int);
+            impl Add for Point { plus = |a, b| (a.0 + b.0, a.1 + b.1) };
+            let origin: Point = (0, 0);
+            let area: (Point) -> Int = |p| p.0 * p.1;
+            let pair = |x| (x, x);
+        "#).unwrap();
+        fs::write(directory.join("a.lang"), r#"import "b.lang" as B;"#).unwrap();
+        fs::write(directory.join("b.lang"), r#"import "a.lang" as A;"#).unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_directory(Some(&directory));
+        let results = run(&mut interpreter, &[
+            r#"import "shapes/geometry.lang" as G; let p: G.Point = (2, 3); (p + G.origin, G.area(p))"#,
+            "(G.pair(1), G.pair(\"s\"))",
+            r#"import "shapes/geometry.lang" as H; equals(H.origin, G.origin)"#,
+            "G.missing",
+            r#"import "a.lang" as A;"#,
+            r#"import "nowhere.lang" as N;"#,
+        ]);
+
+        assert_eq!(results[0], "((2, 3), 6): type (Point, Int)");
+        assert_eq!(results[1], r#"((1, 1), ("s", "s")): type (type (Int, Int), type (String, String))"#);
+        assert_eq!(results[2], "true: Bool");
+        assert!(results[3].starts_with("error: "), "{}", results[3]);
+        let (a, b) = (directory.join("a.lang"), directory.join("b.lang"));
+        assert_eq!(results[4], format!("error: import cycle: {} -> {} -> {}", a.display(), b.display(), a.display()));
+        assert!(results[5].starts_with("error: can't read "), "{}", results[5]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn trait_methods_are_in_scope_where_their_module_is_imported() {
+        let directory = std::env::temp_dir().join(format!("lang-methods-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("greet.lang"), r#"
+            trait Greet { greet: (Self) -> String };
+            impl Greet for Int { greet = |n| "hello ${n}" };
+        "#).unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_directory(Some(&directory));
+        let results = run(&mut interpreter, &[
+            r#"import "greet.lang" as G; (greet(3), G.greet(4))"#,
+            "let twice = |x| greet(x) ++ greet(x); twice(5)",
+            r#"greet("s")"#,
+        ]);
+
+        assert_eq!(results[0], r#"("hello 3", "hello 4"): type (String, String)"#);
+        assert_eq!(results[1], r#""hello 5hello 5": String"#);
+        assert!(results[2].starts_with("error: no implementation of Greet for String"), "{}", results[2]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn the_prelude_is_in_scope_unless_left_out() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "(find([1, 2, 3], |x| equals(x, 2)), join(reverse([\"a\", \"b\", \"c\"]), \", \"), map_option(last([1, 2]), |x| x * 10))",
+            "(sum([1, 2, 3]), flat_map([1, 2], |x| [x, x]), unwrap_or(first([]), 0), includes([1, 2], 3))",
+            "let n: Number = 1.5; let j: Json = \"a\"; (n, j)",
+            "let sum = |xs| length(xs); sum(\"abc\")",
+        ]);
+
+        assert_eq!(results[0], r#"(some(2), "c, b, a", some(20)): type (Int?, String, Int?)"#);
+        assert_eq!(results[1], "(6, [1, 1, 2, 2], 0, false): type (Int, List Int, Int, Bool)");
+        assert_eq!(results[2], r#"(1.5, "a"): type (Number, Json)"#);
+        assert_eq!(results[3], "3: Int");
+
+        let mut interpreter = Interpreter::without_prelude();
+        let results = run(&mut interpreter, &["find([1], |x| true)", "let n: Number = 1;", "length(\"abc\")"]);
+
+        assert!(results[0].starts_with("error: Undeclared variable find"), "{}", results[0]);
+        assert!(results[1].starts_with("error: Undeclared variable Number"), "{}", results[1]);
+        assert_eq!(results[2], "3: Int");
+    }
+
+    #[test]
+    fn failed_statement_leaves_types_alone() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &["let mut xs = [];", r#"xs = [1]; 1 + "a""#, r#"xs = ["s"]; xs"#]);
+
+        assert!(results[1].starts_with("error: "), "{}", results[1]);
+        assert_eq!(results[2], r#"["s"]: List String"#);
+    }
+
+    #[test]
+    fn check_str_leaves_types_alone() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_str("let mut xs = [];").unwrap();
+
+        assert_eq!(interpreter.check_str("xs = [1]; xs").unwrap().to_string(), "List Int");
+        interpreter.elaborate_str("xs = [true];").unwrap();
+        assert_eq!(interpreter.eval_str(r#"xs = ["s"]; xs"#).unwrap().to_string(), r#"["s"]: List String"#);
+    }
+
+    #[test]
+    fn solved_types_are_kept_in_the_session() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_str("let mut xs = []; xs = [1];").unwrap();
+
+        let binding = thread::spawn(move || interpreter.get_binding("xs").unwrap()).join().unwrap();
+        assert_eq!(binding.to_string(), "[1]: List Int");
+    }
+
+    #[test]
+    fn type_variables_in_results_are_shown_as_letters() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let mut g = |x| x; g",
+            "let mut xs = []; (xs, g)",
+        ]);
+
+        assert_eq!(results[0], "<function |x|>: (a) -> a");
+        assert_eq!(results[1], "([], <function |x|>): type (List a, (b) -> b)");
+        assert_eq!(interpreter.get_binding("xs").unwrap().to_string(), "[]: List a");
+    }
+
+    #[test]
+    fn mutable_variables_are_shared_with_closures() {
+        let mut interpreter = Interpreter::new();
+        let results = run(&mut interpreter, &[
+            "let mut total = 0; let add = |n| { total = total + n; total };",
+            "(add(2), add(3), total)",
+            "total = 10; add(1)",
+            r#"total = "s";"#,
+            "let x = 1; x = 2;",
+            "y = 1;",
+            "{let mut n = 1; let f = || n; n = 2; f()}",
+        ]);
+
+        assert_eq!(results[1], "(2, 5, 5): type (Int, Int, Int)");
+        assert_eq!(results[2], "11: Int");
+        assert!(results[3].starts_with("error: expected Int, found String"), "{}", results[3]);
+        assert!(results[4].starts_with("error: can't assign to `x`, which wasn't declared with `let mut`"), "{}", results[4]);
+        assert!(results[5].starts_with("error: Undeclared variable y"), "{}", results[5]);
+        assert_eq!(results[6], "2: Int");
+    }
+}
@@ -0,0 +1,376 @@
+//! Reading and writing values as JSON.
+//!
+//! `Value` and `Type` implement `Serialize` and `Deserialize`, so they work with
+//! any serde format. Values map onto JSON in the obvious way: `nil` and `none`
+//! are `null`, records and maps are objects, and lists, sets and tuples are
+//! arrays. Object keys have to be strings, so Int, Float and Bool keys of maps
+//! are written as strings, and `from_json` turns them back. Ints of any size are
+//! written with all their digits, and Floats that are NaN or infinite are an
+//! error, since JSON has no numbers for them. Deserializing a `Value` without
+//! knowing its type takes the JSON at face value, so arrays always become lists
+//! and objects always become records.
+//! To read data from another service, use `from_json` with the type you expect
+//! instead:
+//!
+//! ```
+//! use lang::{json::from_json, typeck::Type};
+//!
+//! let user = Type::Record(vec![
+//!     ("name".into(), Type::String_),
+//!     ("age".into(), Type::Int),
+//! ].into_iter().collect(), None);
+//!
+//! let value = from_json(r#"{"name": "Ada", "age": 36}"#, &user).unwrap();
+//! assert_eq!(value.to_string(), r#"{name="Ada", age=36}"#);
+//!
+//! let error = from_json(r#"{"name": 36, "age": 36}"#, &user).unwrap_err();
+//! assert_eq!(error.to_string(), ".name: expected String, found Number");
+//! ```
+
+use {
+    crate::{
+        ast::Name,
+        typeck::{Type, substitute_field},
+        util::OrderedMap,
+        vm::Value,
+    },
+    im::Vector,
+    num_bigint::BigInt,
+    num_traits::ToPrimitive,
+    serde::{
+        de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor},
+        ser::{self, Serialize, Serializer, SerializeMap, SerializeSeq},
+    },
+    serde_json::{Number, Value as Json},
+    std::fmt,
+};
+
+/// The name serde_json gives the one field of the map it deserializes numbers as
+const NUMBER_TOKEN: &str = "$serde_json::private::Number";
+
+/// JSON that couldn't be read or written. `path` says where in the document
+/// the problem is, like `.users[2].name`, and is empty for the document itself.
+#[derive(Debug, Clone)]
+pub struct JsonError {
+    pub path: String,
+    pub message: String,
+}
+
+impl JsonError {
+    fn new(message: impl Into<String>) -> Self {
+        JsonError { path: String::new(), message: message.into() }
+    }
+
+    fn within(mut self, segment: impl fmt::Display) -> Self {
+        self.path = format!("{}{}", segment, self.path);
+        self
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(fmt, "{}", self.message)
+        } else {
+            write!(fmt, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(error: serde_json::Error) -> Self {
+        JsonError::new(error.to_string())
+    }
+}
+
+/// Parses `text` as JSON and checks that it is a value of type `expected`.
+/// Fields of objects that aren't in the expected record type are ignored.
+pub fn from_json(text: &str, expected: &Type) -> Result<Value, JsonError> {
+    let json: Json = serde_json::from_str(text)?;
+    decode(&json, expected)
+}
+
+pub fn to_json(value: &Value) -> Result<String, JsonError> {
+    Ok(serde_json::to_string(value)?)
+}
+
+fn json_kind(json: &Json) -> &'static str {
+    match json {
+        Json::Null => "Null",
+        Json::Bool(_) => "Bool",
+        Json::Number(_) => "Number",
+        Json::String(_) => "String",
+        Json::Array(_) => "Array",
+        Json::Object(_) => "Object",
+    }
+}
+
+fn decode(json: &Json, expected: &Type) -> Result<Value, JsonError> {
+    let mismatch = || JsonError::new(format!("expected {}, found {}", expected, json_kind(json)));
+
+    match (expected, json) {
+        (Type::Nil, Json::Null) => Ok(Value::Nil),
+        (Type::Optional(_), Json::Null) => Ok(Value::Optional(None)),
+        (Type::Optional(inner), json) => {
+            Ok(Value::Optional(Some(Box::new(decode(json, inner)?))))
+        }
+        (Type::Bool, Json::Bool(b)) => Ok(Value::Bool(*b)),
+        (Type::String_, Json::String(s)) => Ok(Value::String_(s.clone())),
+        (Type::Literal(literal), Json::String(s)) if s == literal => Ok(Value::String_(s.clone())),
+        (Type::Int, Json::Number(n)) => {
+            // numbers keep all their digits, so Ints of any size can be read
+            match n.to_string().parse::<BigInt>() {
+                Ok(int) => Ok(Value::Int(int)),
+                Err(_) => Err(JsonError::new(format!("expected Int, found fractional Number {}", n))),
+            }
+        }
+        (Type::Float, Json::Number(n)) => {
+            // as_f64 fails for numbers too large for a Float
+            n.as_f64().map(Value::Float).ok_or_else(mismatch)
+        }
+        (Type::Tuple(types), Json::Array(elements)) => {
+            if types.len() != elements.len() {
+                return Err(JsonError::new(format!(
+                    "expected an array of {} elements, found {}", types.len(), elements.len()
+                )));
+            }
+            let mut types = types.clone();
+            let mut values = Vec::new();
+
+            for (i, element) in elements.iter().enumerate() {
+                let value = decode(element, &types[i]).map_err(|e| e.within(format_args!("[{}]", i)))?;
+                substitute_later_fields(&mut types[i + 1..], &i.to_string().into(), &value);
+                values.push(value);
+            }
+
+            Ok(Value::Tuple(values))
+        }
+        (Type::List(element_type), Json::Array(elements)) => {
+            elements.iter().enumerate()
+                .map(|(i, element)| {
+                    decode(element, element_type).map_err(|e| e.within(format_args!("[{}]", i)))
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::List)
+        }
+        (Type::Set(element_type), Json::Array(elements)) => {
+            elements.iter().enumerate()
+                .map(|(i, element)| {
+                    decode(element, element_type).map_err(|e| e.within(format_args!("[{}]", i)))
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::Set)
+        }
+        (Type::Map(key_type, value_type), Json::Object(object)) => {
+            object.iter()
+                .map(|(key, value)| {
+                    let within = |e: JsonError| e.within(format_args!("[{:?}]", key));
+                    let key = decode_key(key, key_type).map_err(within)?;
+                    let value = decode(value, value_type).map_err(within)?;
+                    Ok((key, value))
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::Map)
+        }
+        (Type::Record(fields, None), Json::Object(object)) => {
+            let (names, mut types): (Vec<Name>, Vec<Type>) = fields.clone().into_iter().unzip();
+            let mut values = OrderedMap::default();
+
+            for (i, name) in names.into_iter().enumerate() {
+                let json = object.get(name.as_ref() as &str)
+                    .ok_or_else(|| JsonError::new(format!("missing field `{}`", name)))?;
+                let value = decode(json, &types[i]).map_err(|e| e.within(format_args!(".{}", name)))?;
+                substitute_later_fields(&mut types[i + 1..], &name, &value);
+                values.insert(name, value);
+            }
+
+            Ok(Value::Record(values))
+        }
+        (Type::Type(level), _) => {
+            let ty = Type::deserialize(json)
+                .map_err(|e| JsonError::new(format!("expected {}, found {}: {}", expected, json_kind(json), e)))?;
+            if ty.level() > *level {
+                return Err(JsonError::new(format!("expected a type in {}, found {}, which is in {}", expected, ty, Type::Type(ty.level()))));
+            }
+            Ok(Value::Type(ty))
+        }
+        (Type::Named(..), _) => decode(json, &expected.unfold()),
+        // the first member that the JSON fits is the one it's read as
+        (Type::Union(members), _) => {
+            members.iter()
+                .find_map(|member| decode(json, member).ok())
+                .ok_or_else(|| JsonError::new(format!("expected {}, found {}", expected, json_kind(json))))
+        }
+        (Type::Function(..), _)
+        | (Type::Record(_, Some(_)), _)
+        | (Type::OpenTuple(..), _)
+        | (Type::Var(_), _)
+        | (Type::Param(_), _)
+        | (Type::Field(_), _)
+        | (Type::Recursive(_), _)
+        | (Type::Forall(..), _)
+        | (Type::Error, _) => {
+            Err(JsonError::new(format!("values of type {} can't be read from JSON", expected)))
+        }
+        _ => Err(mismatch()),
+    }
+}
+
+/// JSON objects only have string keys, so `to_json` writes other keys, like
+/// the Ints in a `Map Int String`, as their JSON inside a string
+fn decode_key(key: &str, key_type: &Type) -> Result<Value, JsonError> {
+    decode(&Json::String(key.to_string()), key_type).or_else(|error| {
+        serde_json::from_str(key).ok()
+            .and_then(|json| decode(&json, key_type).ok())
+            .ok_or(error)
+    })
+}
+
+/// In a dependent record or tuple type, the types of later fields can refer to
+/// earlier fields whose values are types, so they're filled in as they're read
+pub(crate) fn substitute_later_fields(later_types: &mut [Type], name: &Name, value: &Value) {
+    if let Value::Type(value) = value {
+        for ty in later_types {
+            *ty = substitute_field(ty, name, value);
+        }
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Record(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (name, value) in fields {
+                    map.serialize_entry(name, value)?;
+                }
+                map.end()
+            }
+            Value::List(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Value::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Value::Set(elements) => {
+                let mut seq = serializer.serialize_seq(Some(elements.len()))?;
+                for element in elements {
+                    seq.serialize_element(element)?;
+                }
+                seq.end()
+            }
+            Value::Tuple(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Value::Int(int) => {
+                if let Some(i) = int.to_i64() {
+                    serializer.serialize_i64(i)
+                } else {
+                    // serde_json writes these digits as they are
+                    Number::from_string_unchecked(int.to_string()).serialize(serializer)
+                }
+            }
+            Value::Float(float) if !float.is_finite() => {
+                Err(ser::Error::custom(format!("can't serialize {}, which isn't a finite number", float)))
+            }
+            Value::Float(float) => serializer.serialize_f64(*float),
+            Value::String_(s) => serializer.serialize_str(s),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Type(ty) => ty.serialize(serializer),
+            Value::Builtin(builtin) => {
+                Err(ser::Error::custom(format!("can't serialize function {}", builtin.name)))
+            }
+            Value::Closure(closure) => {
+                Err(ser::Error::custom(format!("can't serialize function {}", closure)))
+            }
+            Value::Optional(Some(value)) => value.serialize(serializer),
+            Value::Optional(None) => serializer.serialize_unit(),
+            Value::Cell(cell) => cell.get().serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "a value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, i: i64) -> Result<Value, E> {
+        Ok(Value::Int(BigInt::from(i)))
+    }
+
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<Value, E> {
+        Ok(Value::Int(BigInt::from(u)))
+    }
+
+    fn visit_f64<E: de::Error>(self, f: f64) -> Result<Value, E> {
+        Ok(Value::Float(f))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::String_(s.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, s: String) -> Result<Value, E> {
+        Ok(Value::String_(s))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vector::new();
+        while let Some(value) = seq.next_element()? {
+            values.push_back(value);
+        }
+        Ok(Value::List(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut fields = OrderedMap::default();
+        while let Some(name) = map.next_key::<Name>()? {
+            // serde_json hands over numbers as a map with one entry holding their digits
+            if fields.is_empty() && name.as_ref() as &str == NUMBER_TOKEN {
+                let digits = map.next_value::<String>()?;
+                return match digits.parse::<BigInt>() {
+                    Ok(int) => Ok(Value::Int(int)),
+                    Err(_) => digits.parse().map(Value::Float).map_err(de::Error::custom),
+                };
+            }
+            fields.insert(name, map.next_value()?);
+        }
+        Ok(Value::Record(fields))
+    }
+}
@@ -0,0 +1,18 @@
+#![warn(rust_2018_idioms)]
+#![allow(unreachable_pub)]
+
+pub mod ast;
+pub mod builtins;
+pub mod context;
+pub mod convert;
+pub mod graphql;
+mod interpreter;
+pub mod json;
+#[allow(rust_2018_idioms, unused_parens, clippy::all)]
+mod parser;
+pub mod prelude;
+pub mod typeck;
+pub mod util;
+pub mod vm;
+
+pub use crate::interpreter::{Interpreter, Evaluation, Binding, Error};
@@ -1,37 +1,46 @@
 #![warn(rust_2018_idioms)]
 #![allow(unreachable_pub)]
 
-mod ast;
-mod context;
-#[allow(rust_2018_idioms)]
-mod parser;
-mod typeck;
-mod util;
-mod vm;
-
 use {
-    crate::{
-        ast::ReplLineKind,
-        parser::ReplLineParser,
-        typeck::{Type, typeck_stmt, infer_type},
-        vm::{Value, evaluate, evaluate_stmt},
-    },
+    lang::{Interpreter, Error},
     rustyline::{
         error::ReadlineError::{Interrupted, Eof},
     },
 };
 
+fn print_error(error: Error) {
+    match error {
+        Error::Type(errors) => {
+            for error in errors {
+                println!("{} at {}", error.message, error.span);
+                for (note, span) in error.notes {
+                    println!("    note: {} at {}", note, span);
+                }
+            }
+        }
+        Error::Module(path, error) => {
+            println!("in {}:", path.display());
+            print_error(*error);
+        }
+        error => println!("{}", error),
+    }
+}
+
 fn main() {
     let mut line_reader = rustyline::Editor::<()>::new();
 
-    if let Err(_) = line_reader.load_history("history.txt") {
+    if line_reader.load_history("history.txt").is_err() {
         println!("No previous history.");
     }
 
-    let mut type_context = typeck::TypeContext::new();
-    let mut value_context = vm::ValueContext::new();
+    // `--no-prelude` starts out with only the builtins that are written in Rust
+    let mut interpreter = if std::env::args().skip(1).any(|arg| arg == "--no-prelude") {
+        Interpreter::without_prelude()
+    } else {
+        Interpreter::new()
+    };
 
-    'repl: loop {
+    loop {
         let line = match line_reader.readline("> ") {
             Ok(line) => {
                 line_reader.add_history_entry(line.as_str());
@@ -50,88 +59,25 @@ fn main() {
             }
         };
 
-        let repl_line = match ReplLineParser::new().parse(&line) {
-            Ok(repl_line) => repl_line,
-            Err(err) => {
-                println!("{}", err);
-                continue
-            }
-        };
-
-        match &repl_line.kind {
-            ReplLineKind::Block(stmts, expr) => {
-                // type-check and evaluate each statement, replacing type_context and context for each one
-                for stmt in stmts {
-                    match typeck_stmt(&stmt, &type_context) {
-                        Ok(tcx) => type_context = tcx,
-                        Err(errors) => {
-                            for error in errors {
-                                println!("{} at {}", error.message, error.span);
-                            }
-                            continue 'repl
-                        }
-                    }
-
-                    match evaluate_stmt(&stmt, &value_context) {
-                        Ok(vcx) => value_context = vcx,
-                        Err(err) => {
-                            println!("{}", err);
-                            continue 'repl
-                        }
-                    }
+        // `:elab` shows a line the way it is after type checking, without running it
+        if line.starts_with(":elab") {
+            // blank out the command, so that spans in errors still line up with the line
+            let source = line.replacen(":elab", "     ", 1);
+            match interpreter.elaborate_str(&source) {
+                Ok((stmts, expr)) => {
+                    let stmts = stmts.iter().map(|stmt| format!("{}; ", stmt)).collect::<String>();
+                    let expr = expr.map(|expr| expr.to_string()).unwrap_or_default();
+                    println!("{}", (stmts + &expr).trim_end());
                 }
-
-                let (value, ty) = match expr {
-                    None => (Value::Nil, Type::Nil),
-                    Some(expr) => {
-                        let ty = match infer_type(expr, &type_context) {
-                            Ok(ty) => ty,
-                            Err(errors) => {
-                                for error in errors {
-                                    println!("{} at {}", error.message, error.span);
-                                }
-                                continue 'repl
-                            }
-                        };
-
-                        let value = match evaluate(expr, &value_context) {
-                            Ok(value) => value,
-                            Err(err) => {
-                                println!("{}", err);
-                                continue 'repl
-                            }
-                        };
-
-                        (value, ty)
-                    }
-                };
-
-                println!("{}: {}", value, ty);
+                Err(error) => print_error(error),
             }
+            continue
         }
 
-        // let ty = match infer_type(&expr, &type_context){
-        //     Ok(ty) => ty,
-        //     Err(errors) => {
-        //         for error in errors {
-        //             println!("{} at {}", error.message, error.span);
-        //         }
-        //         continue
-        //     }
-        // };
-
-        // println!("type: {}", ty);
-
-        // let context = vm::ValueContext::new();
-        // let value = match vm::evaluate(&expr, &context) {
-        //     Ok(value) => value,
-        //     Err(err) => {
-        //         println!("{}", err);
-        //         continue
-        //     }
-        // };
-
-        // println!("{}", value);
+        match interpreter.eval_str(&line) {
+            Ok(evaluation) => println!("{}", evaluation),
+            Err(error) => print_error(error),
+        }
     }
 
     line_reader.save_history("history.txt").unwrap();
@@ -0,0 +1,35 @@
+use crate::{
+    ast::ReplLineKind,
+    parser::ReplLineParser,
+    typeck::{Substitution, TypeBinding, TypeContext, elaborate_stmt},
+    util::join,
+    vm::{ValueContext, evaluate_stmt},
+};
+
+/// The part of the standard library that's written in the language itself
+pub const SOURCE: &str = include_str!("prelude.lang");
+
+/// Runs the prelude in the given contexts, which should have the builtins in
+/// them, and returns the contexts with its bindings added. The prelude is part
+/// of the binary, so a mistake in it is a bug, and it panics.
+pub fn add_to(type_context: &TypeContext, value_context: &ValueContext) -> (TypeContext, ValueContext) {
+    let repl_line = ReplLineParser::new().parse(SOURCE)
+        .unwrap_or_else(|err| panic!("can't parse the prelude: {}", err));
+
+    let stmts = match repl_line.kind {
+        ReplLineKind::Block(stmts, _) => stmts,
+    };
+
+    let mut substitution = Substitution::default();
+    let (type_context, value_context) = stmts.iter().fold((type_context.clone(), value_context.clone()), |(type_context, value_context), stmt| {
+        let (stmt, type_context) = elaborate_stmt(stmt, &type_context, &mut substitution)
+            .unwrap_or_else(|errors| panic!("type errors in the prelude: {}", join(", ", errors.iter())));
+        let value_context = evaluate_stmt(&stmt, &value_context)
+            .unwrap_or_else(|err| panic!("error running the prelude: {}", err));
+        (type_context, value_context)
+    });
+
+    // every program starts with these contexts and its own substitution
+    let type_context = type_context.map(|binding| TypeBinding {ty: substitution.resolve(&binding.ty), ..binding.clone()});
+    (type_context, value_context)
+}
@@ -1,38 +1,818 @@
 use {
     crate::{
-        ast::{Expr, ExprKind, Stmt, StmtKind, Name, Span},
-        util::{Map, join, mapping},
+        ast::{Expr, ExprKind, Stmt, StmtKind, BinOp, StringPart, Ident, Name, Span},
+        util::{Map, OrderedMap, escape, join},
+        vm::{self, Value},
     },
     derive_more::{Display},
+    lazy_static::lazy_static,
+    regex::{Captures, Regex},
+    serde::{Serialize, Deserialize},
     std::{
         cell::RefCell,
+        fmt,
+        iter,
         mem,
+        sync::atomic::{AtomicU32, Ordering},
     }
 };
 
 pub use crate::context::TypeContext;
 
-#[derive(Debug, Display, Clone, Eq, PartialEq)]
+#[derive(Debug, Display, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::enum_variant_names)]
 pub enum Type {
     #[display(fmt = "Nil")]
     Nil,
-    #[display(fmt = "{{{}}}", r#"join(", ", _0.iter().map(mapping(": ")))"#)]
-    Record(Map<Name, Type>),
+    /// A record with these fields. If there's a second type, the record can
+    /// have more fields, and they're described by that type, which is either
+    /// a record type or a variable standing for the rest of the fields.
+    #[display(fmt = "{}", r#"display_record(_0, _1)"#)]
+    Record(OrderedMap<Name, Type>, Option<Box<Type>>),
     #[display(fmt = "type ({})", r#"join(", ", _0.iter())"#)]
     Tuple(Vec<Type>),
-    #[display(fmt = "Number")]
-    Number,
+    /// A tuple that starts with these elements and has the elements of the
+    /// second type after them. That's a variable standing for the rest of the
+    /// elements until they are known, like the rest of a record's fields.
+    /// It's the type of `p` in `|p| p.0`.
+    #[display(fmt = "type ({}, ..{})", r#"join(", ", _0.iter())"#, _1)]
+    OpenTuple(Vec<Type>, Box<Type>),
+    #[display(fmt = "List {}", r#"display_argument(_0)"#)]
+    List(Box<Type>),
+    #[display(fmt = "Map {} {}", r#"display_map_key(_0)"#, r#"display_argument(_1)"#)]
+    Map(Box<Type>, Box<Type>),
+    #[display(fmt = "Set {}", r#"display_argument(_0)"#)]
+    Set(Box<Type>),
+    #[display(fmt = "Int")]
+    Int,
+    #[display(fmt = "Float")]
+    Float,
     #[display(fmt = "String")]
+    #[serde(rename = "String")]
     String_,
-    #[display(fmt = "Type")]
-    Type,
+    #[display(fmt = "Bool")]
+    Bool,
+    /// The type of just one string, like one of the values of a GraphQL enum.
+    /// It's a String, and a union of them is a String that can only be one of them.
+    #[display(fmt = "\"{}\"", r#"escape(_0)"#)]
+    Literal(String),
+    #[display(fmt = "({}) -> {}", r#"join(", ", _0.iter())"#, _1)]
+    Function(Vec<Type>, Box<Type>),
+    /// A value of the inner type, or `none`
+    #[display(fmt = "{}?", r#"display_optional_inner(_0)"#)]
+    Optional(Box<Type>),
+    /// A value of any of these types. Unions are made with `Type::union`, so
+    /// there are at least two members and none of them are unions
+    #[display(fmt = "{}", r#"join(" | ", _0.iter())"#)]
+    Union(Vec<Type>),
+    /// The types at one level of the universe hierarchy. `Type` (level 0) holds
+    /// the types of ordinary values, and each level is in the one above it, so
+    /// `Type` is a `Type 1` rather than its own type
+    #[display(fmt = "{}", r#"display_universe(*_0)"#)]
+    Type(u32),
+    /// A type that hasn't been inferred yet
+    #[display(fmt = "{}", _0)]
+    Var(TypeVar),
+    /// A type parameter bound by an enclosing `Forall`
+    #[display(fmt = "{}", _0)]
+    Param(Name),
+    /// The value of an earlier field of a dependent record or tuple type, like
+    /// `T` in `{T: Type, x: T}`. Tuple elements are referred to by number.
+    #[display(fmt = ".{}", _0)]
+    Field(Name),
+    /// A type that was given a name with `type Name = ...`, and its definition.
+    /// It's printed by name, and is interchangeable with its definition, except
+    /// that a recursive type is only ever the same as itself
+    #[display(fmt = "{}", _0)]
+    Named(Name, Box<Type>),
+    /// A recursive named type, inside its own definition
+    #[display(fmt = "{}", _0)]
+    Recursive(Name),
+    /// A polymorphic type. Each use of a variable with this type gets its own
+    /// copy of the inner type, with the parameters replaced by new type variables
+    #[display(fmt = "forall {}. {}", r#"join(" ", _0.iter())"#, _1)]
+    Forall(Vec<Name>, Box<Type>),
+    /// A type that can only be used at types that implement some traits, like
+    /// `Show a => (a) -> String`. It's only ever directly inside a `Forall`.
+    #[display(fmt = "{}", r#"display_constrained(_0, _1)"#)]
+    Constrained(Vec<(Name, Type)>, Box<Type>),
     #[display(fmt = "TypeError")]
     Error,
 }
 
+impl Type {
+    /// Returns a copy of this type with `f` applied to each of the types directly inside it
+    fn map(&self, mut f: impl FnMut(&Type) -> Type) -> Type {
+        match self {
+            Type::Record(fields, rest) => {
+                let fields = fields.iter().map(|(name, ty)| (name.clone(), f(ty))).collect();
+                Type::Record(fields, rest.as_ref().map(|rest| Box::new(f(rest))))
+            }
+            Type::Tuple(types) => Type::Tuple(types.iter().map(f).collect()),
+            Type::OpenTuple(types, rest) => {
+                Type::OpenTuple(types.iter().map(&mut f).collect(), Box::new(f(rest)))
+            }
+            Type::List(ty) => Type::List(Box::new(f(ty))),
+            Type::Map(key, value) => Type::Map(Box::new(f(key)), Box::new(f(value))),
+            Type::Set(ty) => Type::Set(Box::new(f(ty))),
+            Type::Function(params, ret) => {
+                Type::Function(params.iter().map(&mut f).collect(), Box::new(f(ret)))
+            }
+            Type::Optional(ty) => Type::Optional(Box::new(f(ty))),
+            Type::Union(types) => Type::Union(types.iter().map(f).collect()),
+            Type::Forall(params, ty) => Type::Forall(params.clone(), Box::new(f(ty))),
+            Type::Constrained(constraints, ty) => {
+                let constraints = constraints.iter().map(|(trait_name, ty)| (trait_name.clone(), f(ty))).collect();
+                Type::Constrained(constraints, Box::new(f(ty)))
+            }
+            Type::Named(name, ty) => Type::Named(name.clone(), Box::new(f(ty))),
+            Type::Nil
+            | Type::Int
+            | Type::Float
+            | Type::String_
+            | Type::Bool
+            | Type::Literal(_)
+            | Type::Type(_)
+            | Type::Var(_)
+            | Type::Param(_)
+            | Type::Field(_)
+            | Type::Recursive(_)
+            | Type::Error => self.clone(),
+        }
+    }
+
+    /// The level of the universe this type is in: 0 for the types of ordinary
+    /// values, and one more than the highest universe that appears inside it
+    /// otherwise, so `List Type` and `{T: Type, x: T}` are both in `Type 1`
+    pub fn level(&self) -> u32 {
+        match self {
+            Type::Type(level) => level + 1,
+            ty => {
+                let mut level = 0;
+                ty.map(|inner| {
+                    level = level.max(inner.level());
+                    inner.clone()
+                });
+                level
+            }
+        }
+    }
+
+    /// The definition of a named type, with the type put back in wherever it
+    /// refers to itself, so that a value of a recursive type can be looked
+    /// inside one level at a time. Other types are returned as they are.
+    pub fn unfold(&self) -> Type {
+        match self {
+            Type::Named(name, definition) => replace_recursive(definition, name, self).unfold(),
+            ty => ty.clone(),
+        }
+    }
+
+    /// The union of `types`. Unions inside it are flattened and duplicates are
+    /// removed, so the union of one type is just that type. A union with an
+    /// error in it is an error.
+    pub fn union(types: impl IntoIterator<Item = Type>) -> Type {
+        let mut members = Vec::new();
+
+        for ty in types {
+            let inner = match ty {
+                Type::Union(inner) => inner,
+                ty => vec![ty],
+            };
+            for ty in inner {
+                if ty == Type::Error {
+                    return Type::Error;
+                }
+                if !members.contains(&ty) {
+                    members.push(ty);
+                }
+            }
+        }
+
+        match members.len() {
+            1 => members.remove(0),
+            _ => Type::Union(members),
+        }
+    }
+
+    /// The type of a method of the trait `trait_name`, whose type for an
+    /// implementation is `ty` with `Self` replaced by the implementing type
+    pub fn method(trait_name: &Name, ty: Type) -> Type {
+        let constraint = (trait_name.clone(), Type::Param("Self".into()));
+        Type::Forall(vec!["Self".into()], Box::new(Type::Constrained(vec![constraint], Box::new(ty))))
+    }
+
+    fn is_recursive(&self) -> bool {
+        match self {
+            Type::Named(name, definition) => refers_to(definition, name),
+            _ => false,
+        }
+    }
+}
+
+/// Whether `ty` contains a reference to the recursive type called `name`
+fn refers_to(ty: &Type, name: &Name) -> bool {
+    match ty {
+        Type::Recursive(inner) => inner == name,
+        // a named type with the same name inside `ty` has its own references
+        Type::Named(inner, _) if inner == name => false,
+        ty => {
+            let mut found = false;
+            ty.map(|inner| {
+                found = found || refers_to(inner, name);
+                inner.clone()
+            });
+            found
+        }
+    }
+}
+
+fn replace_recursive(ty: &Type, name: &Name, replacement: &Type) -> Type {
+    match ty {
+        Type::Recursive(inner) if inner == name => replacement.clone(),
+        Type::Named(inner, _) if inner == name => ty.clone(),
+        ty => ty.map(|ty| replace_recursive(ty, name, replacement)),
+    }
+}
+
+/// `Show a => T`, or `(Show a, Add b) => T`
+fn display_constrained(constraints: &[(Name, Type)], ty: &Type) -> String {
+    let constraints: Vec<String> = constraints.iter()
+        .map(|(trait_name, ty)| format!("{} {}", trait_name, display_map_key(ty)))
+        .collect();
+
+    match constraints.as_slice() {
+        [constraint] => format!("{} => {}", constraint, ty),
+        constraints => format!("({}) => {}", join(", ", constraints), ty),
+    }
+}
+
+fn display_universe(level: u32) -> String {
+    match level {
+        0 => "Type".to_string(),
+        level => format!("Type {}", level),
+    }
+}
+
+/// `{name: String, ..r}`, where `r` stands for the rest of the fields
+fn display_record(fields: &OrderedMap<Name, Type>, rest: &Option<Box<Type>>) -> String {
+    let fields = fields.iter().map(|(name, ty)| format!("{}: {}", name, ty));
+
+    match rest {
+        Some(rest) => format!("{{{}}}", join(", ", fields.chain(iter::once(format!("..{}", rest))))),
+        None => format!("{{{}}}", join(", ", fields)),
+    }
+}
+
+/// `List (Int | String)` isn't the same as `List Int | String`
+fn display_argument(ty: &Type) -> String {
+    match ty {
+        Type::Union(_) => format!("({})", ty),
+        ty => ty.to_string(),
+    }
+}
+
+/// Types that would be ambiguous with a `?` after them need parentheses,
+/// e.g. `(List Int)?` isn't the same as `List Int?`
+fn display_optional_inner(ty: &Type) -> String {
+    match ty {
+        Type::List(_)
+        | Type::Map(..)
+        | Type::Set(_)
+        | Type::Function(..)
+        | Type::Forall(..)
+        | Type::Constrained(..)
+        | Type::Union(_) => {
+            format!("({})", ty)
+        }
+        Type::Type(level) if *level > 0 => format!("({})", ty),
+        ty => ty.to_string(),
+    }
+}
+
+/// The key type of a map type has to be a name or in parentheses
+fn display_map_key(ty: &Type) -> String {
+    match ty {
+        Type::Nil
+        | Type::Int
+        | Type::Float
+        | Type::String_
+        | Type::Bool
+        | Type::Literal(_)
+        | Type::Type(0)
+        | Type::Var(_)
+        | Type::Param(_)
+        | Type::Field(_)
+        | Type::Named(..)
+        | Type::Recursive(_) => ty.to_string(),
+        ty => format!("({})", ty),
+    }
+}
+
+#[derive(Debug, Display, Clone, Copy, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[display(fmt = "?{}", _0)]
+pub struct TypeVar(u32);
+
+/// What the type checker knows about a variable
+#[derive(Debug, Clone)]
+pub struct TypeBinding {
+    pub ty: Type,
+    /// The variable's value, if it's known before the program runs. This is
+    /// what lets `List T` be worked out when `T` is `Int`.
+    pub value: Option<Value>,
+    /// Whether the variable was declared with `let mut`, so it can be assigned to
+    pub mutable: bool,
+}
+
+impl TypeBinding {
+    /// The binding of a variable that holds `value`
+    pub fn for_value(ty: Type, value: &Value) -> Self {
+        TypeBinding {ty, value: Some(value.clone()), mutable: false}
+    }
+
+    /// The binding of a `let mut` variable. Its value can change, so it's never known statically.
+    pub fn mutable(ty: Type) -> Self {
+        TypeBinding {ty, value: None, mutable: true}
+    }
+
+    /// The binding of an earlier field of a dependent record or tuple type,
+    /// whose value will only be known once there's a value of that type
+    fn field(name: Name, ty: Type) -> Self {
+        TypeBinding {ty, value: Some(Value::Type(Type::Field(name))), mutable: false}
+    }
+}
+
+impl From<Type> for TypeBinding {
+    fn from(ty: Type) -> Self {
+        TypeBinding {ty, value: None, mutable: false}
+    }
+}
+
+/// What the type checker has learned about type variables. The types in a
+/// TypeContext can contain variables that have been solved, and they mean
+/// whatever the substitution that goes with the context says they are. It's
+/// only changed when the type checker succeeds, so a program with a type
+/// error doesn't leave anything behind.
+#[derive(Default, Clone)]
+pub struct Substitution {
+    // these are cloned by `attempt`, so they're persistent maps
+    bindings: im::HashMap<TypeVar, Type>,
+    /// Where each type variable was solved
+    origins: im::HashMap<TypeVar, Span>,
+}
+
+impl Substitution {
+    /// Replaces all the type variables in `ty` that have been solved with their solutions
+    pub fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(var) => match self.bindings.get(var) {
+                Some(ty) => self.resolve(ty),
+                None => ty.clone(),
+            },
+            // once the rest of a record's fields are known, they're merged into it.
+            // They go first, the way `{record with ...}` adds fields after the
+            // record's own, and fields replaced with `with` take precedence.
+            Type::Record(fields, Some(rest)) => {
+                let fields = fields.iter().map(|(name, ty)| (name.clone(), self.resolve(ty)));
+
+                match self.resolve(rest) {
+                    Type::Record(mut rest_fields, rest) => {
+                        rest_fields.extend(fields);
+                        Type::Record(rest_fields, rest)
+                    }
+                    rest => Type::Record(fields.collect(), Some(Box::new(rest))),
+                }
+            }
+            Type::OpenTuple(types, rest) => {
+                close_tuple(types.iter().map(|ty| self.resolve(ty)).collect(), self.resolve(rest))
+            }
+            // solving the variables in a union can make members the same
+            Type::Union(types) => Type::union(types.iter().map(|ty| self.resolve(ty))),
+            ty => ty.map(|ty| self.resolve(ty)),
+        }
+    }
+}
+
+thread_local! {
+    /// The substitution that the type checker is working with on this thread.
+    /// `collect_type_errors` sets it to a copy of the caller's, and hands it
+    /// back if there are no errors.
+    static SUBSTITUTION: RefCell<Substitution> = RefCell::new(Substitution::default());
+}
+
+/// Type variables are numbered across all substitutions, on every thread
+static NEXT_VAR: AtomicU32 = AtomicU32::new(0);
+
+fn fresh_var() -> Type {
+    Type::Var(TypeVar(NEXT_VAR.fetch_add(1, Ordering::Relaxed)))
+}
+
+fn resolve(ty: &Type) -> Type {
+    SUBSTITUTION.with(|substitution| substitution.borrow().resolve(ty))
+}
+
+/// The tuple type with `types` followed by the elements of `rest`, which are
+/// merged in if they're known
+fn close_tuple(mut types: Vec<Type>, rest: Type) -> Type {
+    match rest {
+        Type::Tuple(rest_types) => {
+            types.extend(rest_types);
+            Type::Tuple(types)
+        }
+        Type::OpenTuple(rest_types, rest) => {
+            types.extend(rest_types);
+            Type::OpenTuple(types, rest)
+        }
+        rest => Type::OpenTuple(types, Box::new(rest)),
+    }
+}
+
+/// Like `resolve`, but leaves the type variables inside `ty` alone
+fn resolve_head(ty: &Type) -> Type {
+    match ty {
+        Type::Var(var) => {
+            let binding = SUBSTITUTION.with(|substitution| {
+                substitution.borrow().bindings.get(var).cloned()
+            });
+
+            match binding {
+                Some(ty) => resolve_head(&ty),
+                None => ty.clone(),
+            }
+        }
+        Type::OpenTuple(types, rest) => close_tuple(types.clone(), resolve_head(rest)),
+        ty => ty.clone(),
+    }
+}
+
+/// The unsolved type variables in `ty`, in the order they first appear
+fn free_vars(ty: &Type) -> Vec<TypeVar> {
+    fn collect(ty: &Type, vars: &mut Vec<TypeVar>) {
+        match ty {
+            Type::Var(var) => {
+                if !vars.contains(var) {
+                    vars.push(*var);
+                }
+            }
+            ty => {
+                ty.map(|inner| {
+                    collect(inner, vars);
+                    inner.clone()
+                });
+            }
+        }
+    }
+
+    let mut vars = Vec::new();
+    collect(&resolve(ty), &mut vars);
+    vars
+}
+
+/// Makes `ty` polymorphic in the type variables that it doesn't share with
+/// `type_context`, e.g. the type of `|x| x` becomes `forall a. (a) -> a`.
+/// This is what lets a function that is bound with `let` be used at
+/// more than one type. If `expr` is a lambda or a variable, traits that have
+/// to be implemented for the new parameters become constraints, and its value
+/// is elaborated to take their dictionaries. Any other expression is only
+/// evaluated once, so the implementations it needs are found (or defaulted) here.
+fn generalize(ty: &Type, type_context: &TypeContext, expr: &Expr) -> Type {
+    let context_vars: Vec<TypeVar> = type_context.iter()
+        .flat_map(|(_, binding)| free_vars(&binding.ty))
+        .collect();
+    let vars: Vec<TypeVar> = free_vars(ty).into_iter()
+        .filter(|var| !context_vars.contains(var))
+        .collect();
+
+    // the type is left with its solved variables in it, so that errors about
+    // it later on can say where it was inferred
+    if vars.is_empty() {
+        return ty.clone();
+    }
+    let ty = resolve(ty);
+
+    let names: Vec<Name> = (0..vars.len()).map(param_name).collect();
+    let params = vars.iter().zip(&names)
+        .map(|(var, name)| (*var, Type::Param(name.clone())))
+        .collect();
+
+    // the obligations are taken out while they're looked at, since finding an
+    // implementation can report an error
+    let obligations = ERROR_CONTEXT.with(|error_context| mem::take(&mut error_context.borrow_mut().obligations));
+    let mut kept = Vec::new();
+    let mut constraints = Vec::new();
+    let mut dictionaries = Vec::new();
+    for mut obligation in obligations {
+        if obligation.dictionary.is_some() || !free_vars(&obligation.ty).iter().any(|var| vars.contains(var)) {
+            kept.push(obligation);
+            continue;
+        }
+
+        if takes_dictionaries(expr) {
+            let obligation_type = resolve(&obligation.ty);
+            let dictionary = dictionary_name(&obligation.trait_name, &obligation_type);
+            if !dictionaries.contains(&dictionary) {
+                constraints.push((obligation.trait_name.clone(), replace_vars(&obligation_type, &params)));
+                dictionaries.push(dictionary.clone());
+            }
+            obligation.dictionary = Some(dictionary);
+        } else {
+            // one that can't be found has been reported, and is dropped so it isn't again
+            match find_implementation(&obligation) {
+                Some(dictionary) => obligation.dictionary = Some(dictionary),
+                None => continue,
+            }
+        }
+        kept.push(obligation);
+    }
+
+    ERROR_CONTEXT.with(|error_context| {
+        let mut error_context = error_context.borrow_mut();
+        error_context.obligations = kept;
+        if !dictionaries.is_empty() {
+            error_context.elaboration.dictionary_params.push((expr.span.clone(), dictionaries));
+        }
+    });
+
+    let ty = replace_vars(&ty, &params);
+    if constraints.is_empty() {
+        Type::Forall(names, Box::new(ty))
+    } else {
+        Type::Forall(names, Box::new(Type::Constrained(constraints, Box::new(ty))))
+    }
+}
+
+/// Whether the value of `expr` can be elaborated to take dictionaries without
+/// changing what it does, which is only true of lambdas and variables. Making
+/// `(1, to_string)` take them would turn a tuple into a function.
+fn takes_dictionaries(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Lambda(..) | ExprKind::Var(_) => true,
+        ExprKind::Parenthesized(expr) => takes_dictionaries(expr),
+        _ => false,
+    }
+}
+
+/// `a`, `b`, ..., `z`, `a1`, `b1`, ...
+fn param_name(index: usize) -> Name {
+    let letter = (b'a' + (index % 26) as u8) as char;
+
+    match index / 26 {
+        0 => letter.to_string().into(),
+        n => format!("{}{}", letter, n).into(),
+    }
+}
+
+fn replace_vars(ty: &Type, replacements: &Map<TypeVar, Type>) -> Type {
+    match ty {
+        Type::Var(var) => replacements.get(var).cloned().unwrap_or_else(|| ty.clone()),
+        ty => ty.map(|ty| replace_vars(ty, replacements)),
+    }
+}
+
+/// Notes pointing at the places where the type variables in `types` were solved,
+/// to explain where the types in a conflict came from
+fn inference_notes(types: &[&Type]) -> Vec<(String, Span)> {
+    fn collect(ty: &Type, notes: &mut Vec<(String, Span)>) {
+        match ty {
+            Type::Var(var) => {
+                let solution = SUBSTITUTION.with(|substitution| {
+                    let substitution = substitution.borrow();
+                    substitution.bindings.get(var).cloned()
+                        .map(|ty| (ty, substitution.origins[var].clone()))
+                });
+
+                if let Some((ty, origin)) = solution {
+                    if !notes.iter().any(|(_, span)| *span == origin) {
+                        notes.push((format!("inferred to be {} here", resolve(&ty)), origin));
+                    }
+                    collect(&ty, notes);
+                }
+            }
+            ty => {
+                ty.map(|inner| {
+                    collect(inner, notes);
+                    inner.clone()
+                });
+            }
+        }
+    }
+
+    let mut notes = Vec::new();
+    for ty in types {
+        collect(ty, &mut notes);
+    }
+    notes
+}
+
+/// Replaces each `Param` in `ty` that is a key of `params` with its value
+fn substitute_params(ty: &Type, params: &Map<Name, Type>) -> Type {
+    match ty {
+        Type::Param(name) => params.get(name).cloned().unwrap_or_else(|| ty.clone()),
+        Type::Forall(bound, inner) => {
+            // don't substitute parameters that are shadowed
+            let params = params.iter()
+                .filter(|(name, _)| !bound.contains(name))
+                .map(|(name, ty)| (name.clone(), ty.clone()))
+                .collect();
+            Type::Forall(bound.clone(), Box::new(substitute_params(inner, &params)))
+        }
+        ty => ty.map(|ty| substitute_params(ty, params)),
+    }
+}
+
+/// If `ty` is polymorphic, replaces its parameters with fresh type variables.
+/// Its constraints have to be met by the variable at `span`.
+fn instantiate(ty: &Type, span: &Span, type_context: &TypeContext) -> Type {
+    match ty {
+        Type::Forall(params, inner) => {
+            let params = params.iter().map(|param| (param.clone(), fresh_var())).collect();
+
+            match substitute_params(inner, &params) {
+                Type::Constrained(constraints, inner) => {
+                    for (trait_name, ty) in constraints {
+                        require(trait_name, ty, span, type_context);
+                    }
+                    *inner
+                }
+                ty => ty,
+            }
+        }
+        ty => ty.clone(),
+    }
+}
+
+/// Tries to make `a` and `b` the same type by solving type variables,
+/// and returns whether it succeeded. `span` is remembered as the reason
+/// for any solutions, so later errors can point back at it.
+fn unify(a: &Type, b: &Type, span: &Span) -> bool {
+    match (resolve(a), resolve(b)) {
+        (Type::Error, _) | (_, Type::Error) => true,
+        (Type::Var(a), Type::Var(b)) if a == b => true,
+        (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+            // a type can't contain itself
+            if free_vars(&ty).contains(&var) {
+                return false;
+            }
+
+            SUBSTITUTION.with(|substitution| {
+                let mut substitution = substitution.borrow_mut();
+                substitution.bindings.insert(var, ty);
+                substitution.origins.insert(var, span.clone());
+            });
+            true
+        }
+        (Type::Record(a, a_rest), Type::Record(b, b_rest)) => {
+            let shared_fields_unify = a.iter()
+                .filter_map(|(name, a)| b.get(name).map(|b| (a, b)))
+                .all(|(a, b)| unify(a, b, span));
+            if !shared_fields_unify {
+                return false;
+            }
+
+            // the fields that only one side has must be in the other side's rest
+            let only_a: OrderedMap<Name, Type> = a.iter()
+                .filter(|(name, _)| !b.contains_key(*name))
+                .map(|(name, ty)| (name.clone(), ty.clone()))
+                .collect();
+            let only_b: OrderedMap<Name, Type> = b.iter()
+                .filter(|(name, _)| !a.contains_key(*name))
+                .map(|(name, ty)| (name.clone(), ty.clone()))
+                .collect();
+
+            match (a_rest, b_rest) {
+                (None, None) => only_a.is_empty() && only_b.is_empty(),
+                (Some(a_rest), None) => only_a.is_empty() && unify(&a_rest, &Type::Record(only_b, None), span),
+                (None, Some(b_rest)) => only_b.is_empty() && unify(&b_rest, &Type::Record(only_a, None), span),
+                (Some(a_rest), Some(b_rest)) => {
+                    if only_a.is_empty() && only_b.is_empty() {
+                        unify(&a_rest, &b_rest, span)
+                    } else if a_rest == b_rest {
+                        // the same rest can't have different extra fields on each side
+                        false
+                    } else {
+                        let rest = Some(Box::new(fresh_var()));
+                        unify(&a_rest, &Type::Record(only_b, rest.clone()), span)
+                            && unify(&b_rest, &Type::Record(only_a, rest), span)
+                    }
+                }
+            }
+        }
+        (Type::Tuple(a), Type::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| unify(a, b, span))
+        }
+        // the elements that only the longer side has must be in the shorter side's rest
+        (Type::OpenTuple(a, a_rest), Type::Tuple(b)) | (Type::Tuple(b), Type::OpenTuple(a, a_rest)) => {
+            a.len() <= b.len()
+                && a.iter().zip(&b).all(|(a, b)| unify(a, b, span))
+                && unify(&a_rest, &Type::Tuple(b[a.len()..].to_vec()), span)
+        }
+        (Type::OpenTuple(a, a_rest), Type::OpenTuple(b, b_rest)) => {
+            let (shorter, shorter_rest, longer, longer_rest) = if a.len() <= b.len() {
+                (a, a_rest, b, b_rest)
+            } else {
+                (b, b_rest, a, a_rest)
+            };
+
+            shorter.iter().zip(&longer).all(|(a, b)| unify(a, b, span))
+                && unify(&shorter_rest, &close_tuple(longer[shorter.len()..].to_vec(), *longer_rest), span)
+        }
+        (Type::List(a), Type::List(b)) => unify(&a, &b, span),
+        (Type::Map(a_key, a_value), Type::Map(b_key, b_value)) => {
+            unify(&a_key, &b_key, span) && unify(&a_value, &b_value, span)
+        }
+        (Type::Set(a), Type::Set(b)) => unify(&a, &b, span),
+        (Type::Optional(a), Type::Optional(b)) => unify(&a, &b, span),
+        (Type::Function(a_params, a_ret), Type::Function(b_params, b_ret)) => {
+            a_params.len() == b_params.len()
+                && a_params.iter().zip(&b_params).all(|(a, b)| unify(a, b, span))
+                && unify(&a_ret, &b_ret, span)
+        }
+        (Type::Union(a), Type::Union(b)) => {
+            a.len() == b.len() && a.iter().all(|a| b.iter().any(|b| attempt(|| unify(a, b, span))))
+        }
+        (Type::Named(a_name, a_definition), Type::Named(b_name, b_definition)) if a_name == b_name => {
+            unify(&a_definition, &b_definition, span)
+        }
+        // unfolding two different recursive types to compare them could go on forever
+        (a @ Type::Named(..), b @ Type::Named(..)) if a.is_recursive() && b.is_recursive() => false,
+        (a @ Type::Named(..), b) => unify(&a.unfold(), &b, span),
+        (a, b @ Type::Named(..)) => unify(&a, &b.unfold(), span),
+        (a, b) => a == b,
+    }
+}
+
+/// Runs `f`, which tries to unify some types, and forgets about any type
+/// variables that it solved if it fails, so that something else can be tried
+fn attempt(f: impl FnOnce() -> bool) -> bool {
+    let saved = SUBSTITUTION.with(|substitution| {
+        let substitution = substitution.borrow();
+        (substitution.bindings.clone(), substitution.origins.clone())
+    });
+
+    let succeeded = f();
+    if !succeeded {
+        SUBSTITUTION.with(|substitution| {
+            let mut substitution = substitution.borrow_mut();
+            (substitution.bindings, substitution.origins) = saved;
+        });
+    }
+    succeeded
+}
+
+/// Whether a value of type `found` can be used where a value of type `expected`
+/// is wanted, solving type variables to make it so. That's when they unify, or
+/// when `expected` is a union and `found` is one of its members (or a union of
+/// some of them), or when that's true of the types inside them: tuples and
+/// records field by field, and the elements of lists, sets, maps and optionals.
+fn is_subtype(found: &Type, expected: &Type, span: &Span) -> bool {
+    let (found_head, expected_head) = (resolve_head(found), resolve_head(expected));
+
+    match (&found_head, &expected_head) {
+        // a recursive type is only ever the same as itself, and unfolding two
+        // of them to compare them could go on forever
+        (Type::Named(found_name, _), Type::Named(expected_name, _))
+            if found_name == expected_name || (found_head.is_recursive() && expected_head.is_recursive()) =>
+        {
+            return unify(found, expected, span);
+        }
+        _ => (),
+    }
+
+    match (found_head.unfold(), expected_head.unfold()) {
+        // which member a type variable is can't be known yet, so it stands for the whole union
+        (Type::Var(_), _) | (_, Type::Var(_)) => unify(found, expected, span),
+        (Type::Union(found_members), _) => {
+            found_members.iter().all(|member| is_subtype(member, expected, span))
+        }
+        (_, Type::Union(members)) => {
+            members.iter().any(|member| attempt(|| is_subtype(found, member, span)))
+        }
+        (Type::Tuple(found_types), Type::Tuple(expected_types)) if found_types.len() == expected_types.len() => {
+            found_types.iter().zip(&expected_types).all(|(found, expected)| is_subtype(found, expected, span))
+        }
+        (Type::Record(found_fields, None), Type::Record(expected_fields, None))
+            if found_fields.len() == expected_fields.len()
+                && found_fields.keys().all(|name| expected_fields.contains_key(name))
+                && !expected_fields.values().any(|ty| !field_refs(ty).is_empty()) =>
+        {
+            found_fields.iter().all(|(name, found)| is_subtype(found, &expected_fields[name], span))
+        }
+        (Type::Literal(_), Type::String_) => true,
+        (Type::List(found), Type::List(expected))
+        | (Type::Set(found), Type::Set(expected))
+        | (Type::Optional(found), Type::Optional(expected)) => is_subtype(&found, &expected, span),
+        (Type::Map(found_key, found_value), Type::Map(expected_key, expected_value)) => {
+            unify(&found_key, &expected_key, span) && is_subtype(&found_value, &expected_value, span)
+        }
+        _ => unify(found, expected, span),
+    }
+}
+
 pub struct ErrorContext {
     in_use: bool,
     errors: Vec<TypeError>,
+    obligations: Vec<Obligation>,
+    elaboration: Elaboration,
 }
 
 impl ErrorContext {
@@ -40,17 +820,48 @@ impl ErrorContext {
         Self {
             in_use: false,
             errors: Vec::new(),
+            obligations: Vec::new(),
+            elaboration: Elaboration::default(),
         }
     }
 }
 
+/// A place where a trait has to be implemented for a type: a use of one of
+/// its methods, of a function with a constraint, or of an operator
+struct Obligation {
+    trait_name: Name,
+    ty: Type,
+    span: Span,
+    /// Where the implementation is looked for
+    type_context: TypeContext,
+    /// The variable holding the implementation's dictionary, once it's known
+    dictionary: Option<Name>,
+}
+
+/// What `elaborate` adds to a program, keyed by the spans of the expressions
+/// that it's added to
+#[derive(Default)]
+struct Elaboration {
+    /// Values that are used as values of a union type, and the union type
+    coercions: Vec<(Span, Type)>,
+    /// Generalized values that take dictionaries, and the names of their parameters
+    dictionary_params: Vec<(Span, Vec<Name>)>,
+    /// The dictionaries that are passed to the variables and operators that need them
+    dictionaries: Vec<(Span, Name)>,
+}
+
 thread_local! {
     static ERROR_CONTEXT: RefCell<ErrorContext> = RefCell::new(ErrorContext::new());
 }
 
+#[derive(Debug, Display, Clone)]
+#[display(fmt = "{} at {}", message, span)]
 pub struct TypeError {
     pub message: String,
     pub span: Span,
+    /// Other places in the source that are relevant to the error,
+    /// e.g. the first definition of a duplicated name
+    pub notes: Vec<(String, Span)>,
 }
 
 impl TypeError {
@@ -68,6 +879,7 @@ macro_rules! type_error {
         let error = TypeError {
             message: format!($($fmt_args)*),
             span: $span.clone(),
+            notes: Vec::new(),
         };
 
         error.emit();
@@ -78,111 +890,668 @@ macro_rules! type_error {
 
 /// Takes a function that potentially stores type errors in ERROR_CONTEXT,
 /// and returns Err(Vec<TypeError>) if there are errors, and
-/// Ok(T) otherwise
-fn collect_type_errors<T>(f: impl FnOnce() -> T) -> Result<T, Vec<TypeError>> {
+/// Ok(T) otherwise. `f` works with a copy of `substitution`, which replaces
+/// it if there are no errors.
+fn collect_type_errors<T>(substitution: &mut Substitution, f: impl FnOnce() -> T) -> Result<T, Vec<TypeError>> {
     ERROR_CONTEXT.with(|error_context| {
         assert!(!error_context.borrow().in_use);
         error_context.borrow_mut().in_use = true;
     });
+    SUBSTITUTION.with(|current| *current.borrow_mut() = substitution.clone());
 
     let value = f();
 
+    let solved = SUBSTITUTION.with(|current| mem::take(&mut *current.borrow_mut()));
     ERROR_CONTEXT.with(|error_context| {
         let error_context = mem::replace(&mut *error_context.borrow_mut(), ErrorContext::new());
         assert!(error_context.in_use);
 
-        if error_context.errors.len() == 0 {
+        if error_context.errors.is_empty() {
+            *substitution = solved;
             Ok(value)
         } else {
-            Err(error_context.errors)
+            Err(error_context.errors.into_iter().map(name_type_vars).collect())
         }
     })
 }
 
-pub fn infer_type(expr: &Expr, type_context: &TypeContext) -> Result<Type, Vec<TypeError>> {
-    collect_type_errors(|| infer_type_internal(expr, type_context))
+/// Type variables are displayed like `?12`, which means nothing to whoever reads
+/// an error, so they're renamed to letters, the same way in the message and its
+/// notes. Names that the error already uses, like the parameters of a
+/// polymorphic type, are skipped.
+fn name_type_vars(error: TypeError) -> TypeError {
+    let texts = iter::once(&error.message).chain(error.notes.iter().map(|(note, _)| note));
+    let mut renamed = rename_type_vars(texts).into_iter();
+
+    TypeError {
+        message: renamed.next().unwrap(),
+        span: error.span,
+        notes: error.notes.into_iter().map(|(_, span)| (renamed.next().unwrap(), span)).collect(),
+    }
 }
 
-fn infer_type_internal(expr: &Expr, type_context: &TypeContext) -> Type {
-    match &expr.kind {
-        ExprKind::Nil => Type::Nil,
+/// Displays `ty` with its type variables renamed to letters, like the types in
+/// errors, for showing the type of a result
+pub fn show_type(ty: &Type) -> String {
+    rename_type_vars(iter::once(&ty.to_string())).remove(0)
+}
 
-        ExprKind::NumberLiteral(_) => Type::Number,
-        ExprKind::StringLiteral(_) => Type::String_,
+/// Renames each type variable in `texts` to a letter that none of them use
+/// already, giving a variable the same letter everywhere it appears
+fn rename_type_vars<'a>(texts: impl Iterator<Item = &'a String> + Clone) -> Vec<String> {
+    lazy_static! {
+        static ref TYPE_VAR: Regex = Regex::new(r"\?[0-9]+").unwrap();
+        static ref WORD: Regex = Regex::new(r"\b\w+\b").unwrap();
+    }
 
-        ExprKind::Tuple(vec) => {
-            // TODO: support dependent tuples
-            Type::Tuple(vec.iter().map(|e| infer_type_internal(e, type_context)).collect())
-        }
-        ExprKind::TupleType(vec) => {
-            vec.iter().for_each(|ty_expr| {
-                match infer_type_internal(ty_expr, type_context) {
-                    Type::Type | Type::Error => (),
-                    ty => {
-                        type_error!(
-                            ty_expr.span,
-                            "expected type of tuple field to be a type, found a {}",
-                            ty,
-                        );
-                    }
-                }
-            });
+    let taken: Vec<&str> = texts.clone().flat_map(|text| WORD.find_iter(text).map(|word| word.as_str())).collect();
+    let mut fresh_names = (0..).map(param_name).filter(|name| !taken.contains(&name.as_ref()));
+    let mut names: Map<String, Name> = Map::default();
 
-            Type::Type
-        }
-        ExprKind::TupleFieldAccess(tuple_expr, number) => {
-            let tuple_type = infer_type_internal(tuple_expr, type_context);
+    texts
+        .map(|text| TYPE_VAR.replace_all(text, |captures: &Captures<'_>| {
+            names.entry(captures[0].to_string())
+                .or_insert_with(|| fresh_names.next().unwrap())
+                .to_string()
+        }).into_owned())
+        .collect()
+}
 
-            match &tuple_type {
-                Type::Tuple(field_types) => {
-                    if let Some(field_type) = field_types.get(*number){
-                        field_type.clone()
-                    } else {
-                        type_error!(
-                            expr.span,
-                            "field number {} is out of range for tuple {}",
-                            number, tuple_type
-                        )
-                    }
-                }
-                _ => type_error!(
-                    tuple_expr.span,
-                    "expected a tuple with at least {} elements, found {}",
-                    number + 1, tuple_type
-                )
-            }
-        }
+/// Emits an error for each name in `idents` that has already appeared earlier in the list,
+/// pointing at both occurrences. `what` describes the names, e.g. "record field".
+fn check_duplicate_names<'a>(what: &str, idents: impl IntoIterator<Item=&'a Ident>) {
+    let mut seen: Map<&Name, &Ident> = Map::default();
 
-        ExprKind::RecordValue(pairs) => {
-            // TODO: handle dependent records
-            Type::Record(pairs.iter().map(|(ident, expr)| {
-                (ident.name.clone(), infer_type_internal(expr, type_context))
-            }).collect())
+    for ident in idents {
+        if let Some(first) = seen.get(&ident.name) {
+            TypeError {
+                message: format!("duplicate {} `{}`", what, ident.name),
+                span: ident.span.clone(),
+                notes: vec![(format!("`{}` first defined here", ident.name), first.span.clone())],
+            }.emit();
+        } else {
+            seen.insert(&ident.name, ident);
         }
-        ExprKind::RecordType(pairs) => {
-            pairs.iter().for_each(|(_, ty_expr)| {
-                match infer_type_internal(ty_expr, type_context) {
-                    Type::Type | Type::Error => (),
-                    ty => {
-                        type_error!(
-                            ty_expr.span,
-                            "expected type of record field to be a type, found a {}",
-                            ty,
-                        );
-                    }
-                }
-            });
+    }
+}
 
-            Type::Type
+/// Emits an error for each key of a map literal that's written the same way as
+/// an earlier one. Keys that are worked out while the program runs can still
+/// turn out to be the same; the VM catches those.
+fn check_duplicate_keys(entries: &[(Expr, Expr)]) {
+    fn literal(key: &Expr) -> Option<String> {
+        match &key.kind {
+            ExprKind::IntLiteral(int) => Some(int.to_string()),
+            ExprKind::StringLiteral(string) => Some(format!("{:?}", string)),
+            ExprKind::Negate(inner) => match &inner.kind {
+                ExprKind::IntLiteral(int) => Some((-int).to_string()),
+                _ => None,
+            },
+            ExprKind::Parenthesized(inner) => literal(inner),
+            _ => None,
         }
-        ExprKind::RecordFieldAccess(..) => unimplemented!("RecordFieldAccess"),
+    }
 
-        ExprKind::Block(stmts, expr) => {
-            let type_context = stmts.iter().fold(type_context.clone(), |type_context, stmt| {
-                typeck_stmt_internal(stmt, &type_context)
-            });
+    let mut seen: Map<String, &Expr> = Map::default();
 
-            match expr {
+    for (key, _) in entries {
+        let literal = match literal(key) {
+            Some(literal) => literal,
+            None => continue,
+        };
+        if let Some(first) = seen.get(&literal) {
+            TypeError {
+                message: format!("duplicate map key `{}`", literal),
+                span: key.span.clone(),
+                notes: vec![(format!("`{}` first used here", literal), first.span.clone())],
+            }.emit();
+        } else {
+            seen.insert(literal, key);
+        }
+    }
+}
+
+pub fn infer_type(expr: &Expr, type_context: &TypeContext, substitution: &mut Substitution) -> Result<Type, Vec<TypeError>> {
+    elaborate(expr, type_context, substitution).map(|(_, ty)| ty)
+}
+
+/// Infers the type of `expr` like `infer_type`, and also returns a copy of it
+/// with what the type checker worked out made explicit: a `Coerce` around each
+/// value that's used as a value of a union type, and the dictionaries of trait
+/// implementations passed to the things that need them
+pub fn elaborate(
+    expr: &Expr,
+    type_context: &TypeContext,
+    substitution: &mut Substitution,
+) -> Result<(Expr, Type), Vec<TypeError>> {
+    collect_type_errors(substitution, || {
+        let ty = generalize(&infer_type_internal(expr, type_context), type_context, expr);
+        (elaborate_expr(expr, &mut finish()), ty)
+    })
+}
+
+/// Checks that `expr` has type `expected`. This gives better errors than
+/// inferring a type and comparing it, and lets lambdas leave out the types
+/// of their parameters.
+pub fn check_type(
+    expr: &Expr,
+    expected: &Type,
+    type_context: &TypeContext,
+    substitution: &mut Substitution,
+) -> Result<(), Vec<TypeError>> {
+    collect_type_errors(substitution, || {
+        check_type_internal(expr, expected, type_context);
+        finish();
+    })
+}
+
+/// The type that a type annotation is, which has to be known before the program runs
+fn annotated_type(ty_expr: &Expr, type_context: &TypeContext) -> Type {
+    if expect_type_expr(ty_expr, "annotation", type_context).is_none() {
+        return Type::Error;
+    }
+
+    match normalize(ty_expr, type_context) {
+        Some(ty) => ty,
+        None => type_error!(ty_expr.span, "can't work out which type this is before the program runs"),
+    }
+}
+
+/// Emits an error unless `ty_expr` is a type, e.g. one of the fields of a record
+/// type, and returns the level of the universe it's in if it doesn't contain
+/// any errors
+fn expect_type_expr(ty_expr: &Expr, what: &str, type_context: &TypeContext) -> Option<u32> {
+    let ty = infer_type_internal(ty_expr, type_context);
+
+    match resolve(&ty) {
+        Type::Type(level) => Some(level),
+        Type::Error => None,
+        // something we don't know much about yet, like a lambda's parameter,
+        // is taken to be an ordinary type
+        var @ Type::Var(_) => {
+            unify(&var, &Type::Type(0), &ty_expr.span);
+            Some(0)
+        }
+        ty => {
+            type_error!(ty_expr.span, "expected type of {} to be a type, found a {}", what, ty);
+            None
+        }
+    }
+}
+
+/// Works out which type `expr` is, if that can be done before the program runs,
+/// so that types can be given names and computed by builtins like `Option(T)`
+/// and `graphql_type`. Types that are worked out this way are compared by what
+/// they are, not by how they were written.
+fn normalize(expr: &Expr, type_context: &TypeContext) -> Option<Type> {
+    match evaluate_statically(expr, type_context)? {
+        Value::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
+/// Evaluates `expr` while type checking, if everything it needs is known. This
+/// covers literals, variables with known values, the syntax for types, and calls
+/// to builtins that return types. Anything else is left until the program runs.
+fn evaluate_statically(expr: &Expr, type_context: &TypeContext) -> Option<Value> {
+    let normalize_inner = |expr: &Expr| normalize(expr, type_context).map(Box::new);
+
+    Some(Value::Type(match &expr.kind {
+        ExprKind::Nil => return Some(Value::Nil),
+        ExprKind::IntLiteral(int) => return Some(Value::Int(int.clone())),
+        ExprKind::FloatLiteral(float) => return Some(Value::Float(*float)),
+        ExprKind::StringLiteral(s) => return Some(Value::String_(s.clone())),
+        ExprKind::Var(ident) => return type_context.lookup(&ident.name)?.value.clone(),
+        ExprKind::Parenthesized(expr) => return evaluate_statically(expr, type_context),
+        // the only records whose values are known are modules
+        ExprKind::RecordFieldAccess(record, field) => match evaluate_statically(record, type_context)? {
+            Value::Record(fields) => return fields.get(&field.name).cloned(),
+            _ => return None,
+        },
+        ExprKind::Call(function, args) => {
+            let binding = match &function.kind {
+                ExprKind::Var(ident) => type_context.lookup(&ident.name)?,
+                _ => return None,
+            };
+
+            // builtins are called as long as they return types. Closures aren't,
+            // since they might not finish
+            match (&binding.value, &binding.ty) {
+                (Some(builtin @ Value::Builtin(_)), Type::Function(_, return_type))
+                    if matches!(**return_type, Type::Type(_)) =>
+                {
+                    let args = args.iter()
+                        .map(|arg| evaluate_statically(arg, type_context))
+                        .collect::<Option<_>>()?;
+                    return vm::call(builtin, args).ok();
+                }
+                _ => return None,
+            }
+        }
+        ExprKind::NilType => Type::Nil,
+        ExprKind::Universe(level) => Type::Type(*level),
+        ExprKind::ListType(ty_expr) => Type::List(normalize_inner(ty_expr)?),
+        ExprKind::SetType(ty_expr) => Type::Set(normalize_inner(ty_expr)?),
+        ExprKind::OptionalType(ty_expr) => Type::Optional(normalize_inner(ty_expr)?),
+        ExprKind::UnionType(lhs, rhs) => {
+            Type::union(vec![normalize(lhs, type_context)?, normalize(rhs, type_context)?])
+        }
+        ExprKind::MapType(key_expr, value_expr) => {
+            Type::Map(normalize_inner(key_expr)?, normalize_inner(value_expr)?)
+        }
+        ExprKind::FunctionType(params, ret) => {
+            let params = params.iter().map(|param| normalize(param, type_context)).collect::<Option<_>>()?;
+            Type::Function(params, normalize_inner(ret)?)
+        }
+        ExprKind::RecordType(pairs) => {
+            let mut type_context = type_context.clone();
+            let mut fields = OrderedMap::default();
+
+            for (ident, ty_expr) in pairs {
+                let ty = normalize(ty_expr, &type_context)?;
+                type_context = type_context.extend(ident.name.clone(), TypeBinding::field(ident.name.clone(), ty.clone()));
+                fields.insert(ident.name.clone(), ty);
+            }
+
+            Type::Record(fields, None)
+        }
+        ExprKind::TupleType(elements) => {
+            let mut type_context = type_context.clone();
+            let mut types = Vec::new();
+
+            for (number, (ident, ty_expr)) in elements.iter().enumerate() {
+                let ty = normalize(ty_expr, &type_context)?;
+                if let Some(ident) = ident {
+                    type_context = type_context.extend(ident.name.clone(), TypeBinding::field(number.to_string().into(), ty.clone()));
+                }
+                types.push(ty);
+            }
+
+            Type::Tuple(types)
+        }
+        _ => return None,
+    }))
+}
+
+/// The earlier fields that `ty` refers to, if it's the type of a field of a
+/// dependent record or tuple
+fn field_refs(ty: &Type) -> Vec<Name> {
+    match ty {
+        Type::Field(name) => vec![name.clone()],
+        Type::Record(fields, rest) => {
+            let names = fields.keys().cloned();
+            let types = fields.values().chain(rest.as_ref().map(AsRef::as_ref));
+            nested_field_refs(names, types)
+        }
+        Type::Tuple(types) => {
+            let names = (0..types.len()).map(|number| number.to_string().into());
+            nested_field_refs(names, types)
+        }
+        Type::Function(params, ret) => {
+            let names = (0..params.len()).map(|number| number.to_string().into());
+            nested_field_refs(names, params.iter().chain(iter::once(&**ret)))
+        }
+        ty => {
+            let mut refs = Vec::new();
+            ty.map(|inner| {
+                refs.extend(field_refs(inner));
+                inner.clone()
+            });
+            refs
+        }
+    }
+}
+
+/// The references to fields outside of a record or tuple type that has fields
+/// called `names`, whose types are `types`. Each field's name refers to that
+/// field in the types after it.
+fn nested_field_refs<'a>(names: impl Iterator<Item=Name>, types: impl IntoIterator<Item=&'a Type>) -> Vec<Name> {
+    let mut refs = Vec::new();
+    let mut inner_names = Vec::new();
+
+    for (ty, name) in types.into_iter().zip(names.map(Some).chain(iter::repeat(None))) {
+        refs.extend(field_refs(ty).into_iter().filter(|name| !inner_names.contains(name)));
+        inner_names.extend(name);
+    }
+
+    refs
+}
+
+/// Replaces references to the field `name` in `ty` with `value`, which is what
+/// that field turned out to be
+pub(crate) fn substitute_field(ty: &Type, name: &Name, value: &Type) -> Type {
+    // a field with the same name inside `ty` hides this one in the types after it
+    let mut hidden = false;
+    let mut substitute_unless_hidden = |ty: &Type, inner_name: Option<Name>| {
+        let ty = if hidden { ty.clone() } else { substitute_field(ty, name, value) };
+        hidden = hidden || inner_name.as_ref() == Some(name);
+        ty
+    };
+
+    match ty {
+        Type::Field(field) if field == name => value.clone(),
+        Type::Record(fields, rest) => {
+            let fields = fields.iter()
+                .map(|(inner_name, ty)| (inner_name.clone(), substitute_unless_hidden(ty, Some(inner_name.clone()))))
+                .collect();
+            let rest = rest.as_ref().map(|rest| Box::new(substitute_unless_hidden(rest, None)));
+            Type::Record(fields, rest)
+        }
+        Type::Tuple(types) => {
+            Type::Tuple(types.iter().enumerate()
+                .map(|(number, ty)| substitute_unless_hidden(ty, Some(number.to_string().into())))
+                .collect())
+        }
+        Type::Function(params, ret) => {
+            let params = params.iter().enumerate()
+                .map(|(number, ty)| substitute_unless_hidden(ty, Some(number.to_string().into())))
+                .collect();
+            Type::Function(params, Box::new(substitute_unless_hidden(ret, None)))
+        }
+        ty => ty.map(|ty| substitute_field(ty, name, value)),
+    }
+}
+
+/// Emits an error if `field_type`, the type of a field that is being read, depends
+/// on other fields of its record or tuple, since their values aren't known until
+/// the program runs
+fn expect_independent_field(field_type: Type, field: &dyn fmt::Display, span: &Span) -> Type {
+    match field_refs(&field_type).first() {
+        Some(dependency) => type_error!(
+            span,
+            "the type of field `{}` depends on the value of field `{}`, which isn't known until the program runs",
+            field, dependency,
+        ),
+        None => field_type,
+    }
+}
+
+fn infer_type_internal(expr: &Expr, type_context: &TypeContext) -> Type {
+    match &expr.kind {
+        ExprKind::Nil => Type::Nil,
+
+        ExprKind::IntLiteral(_) => Type::Int,
+        ExprKind::FloatLiteral(_) => Type::Float,
+        ExprKind::StringLiteral(_) => Type::String_,
+        ExprKind::Interpolated(parts) => {
+            // values are rendered with their implementations of Show, which
+            // are passed to the string in the order of its parts
+            for part in parts {
+                if let StringPart::Expr(part) = part {
+                    let ty = infer_type_internal(part, type_context);
+                    require("Show".into(), ty, &expr.span, type_context);
+                }
+            }
+
+            Type::String_
+        }
+
+        ExprKind::Tuple(vec) => {
+            Type::Tuple(vec.iter().map(|e| infer_type_internal(e, type_context)).collect())
+        }
+        ExprKind::TupleType(elements) => {
+            check_duplicate_names("tuple field", elements.iter().filter_map(|(ident, _)| ident.as_ref()));
+
+            // named elements are in scope in the types after them
+            let mut level = 0;
+            elements.iter().enumerate().fold(type_context.clone(), |type_context, (number, (ident, ty_expr))| {
+                level = level.max(expect_type_expr(ty_expr, "tuple field", &type_context).unwrap_or(0));
+
+                match ident {
+                    Some(ident) => {
+                        let ty = normalize(ty_expr, &type_context).unwrap_or_else(fresh_var);
+                        type_context.extend(ident.name.clone(), TypeBinding::field(number.to_string().into(), ty))
+                    }
+                    None => type_context,
+                }
+            });
+
+            Type::Type(level)
+        }
+        ExprKind::List(elements) => {
+            let element_type = fresh_var();
+
+            for element in elements {
+                check_type_internal(element, &element_type, type_context);
+            }
+
+            Type::List(Box::new(element_type))
+        }
+        ExprKind::ListType(ty_expr) => {
+            Type::Type(expect_type_expr(ty_expr, "list element", type_context).unwrap_or(0))
+        }
+        ExprKind::Map(entries) => {
+            check_duplicate_keys(entries);
+            let key_type = fresh_var();
+            let value_type = fresh_var();
+
+            for (key, value) in entries {
+                check_type_internal(key, &key_type, type_context);
+                check_type_internal(value, &value_type, type_context);
+            }
+
+            Type::Map(Box::new(key_type), Box::new(value_type))
+        }
+        ExprKind::MapType(key_expr, value_expr) => {
+            let key_level = expect_type_expr(key_expr, "map key", type_context);
+            let value_level = expect_type_expr(value_expr, "map value", type_context);
+
+            Type::Type(key_level.max(value_level).unwrap_or(0))
+        }
+        ExprKind::FunctionType(params, ret) => {
+            let param_levels: Vec<Option<u32>> = params.iter()
+                .map(|param| expect_type_expr(param, "function parameter", type_context))
+                .collect();
+            let return_level = expect_type_expr(ret, "function return", type_context);
+
+            Type::Type(param_levels.into_iter().chain(iter::once(return_level)).flatten().max().unwrap_or(0))
+        }
+        ExprKind::Set(elements) => {
+            let element_type = fresh_var();
+
+            for element in elements {
+                check_type_internal(element, &element_type, type_context);
+            }
+
+            Type::Set(Box::new(element_type))
+        }
+        ExprKind::SetType(ty_expr) => {
+            Type::Type(expect_type_expr(ty_expr, "set element", type_context).unwrap_or(0))
+        }
+        ExprKind::TupleFieldAccess(tuple_expr, number) => {
+            let tuple_type = infer_type_internal(tuple_expr, type_context);
+
+            match resolve(&tuple_type).unfold() {
+                Type::Optional(_) => type_error!(
+                    tuple_expr.span,
+                    "expected a tuple, found optional {}; use `?.{}` or check it with `if let`",
+                    resolve(&tuple_type), number,
+                ),
+                _ => tuple_element_type(&tuple_type, *number, &tuple_expr.span, &expr.span),
+            }
+        }
+        ExprKind::OptionalTupleFieldAccess(tuple_expr, number) => {
+            let optional_type = infer_type_internal(tuple_expr, type_context);
+            let tuple_type = expect_optional(&optional_type, "?.", &tuple_expr.span);
+
+            match tuple_type {
+                Type::Error => Type::Error,
+                tuple_type => optional(tuple_element_type(&tuple_type, *number, &tuple_expr.span, &expr.span)),
+            }
+        }
+
+        ExprKind::RecordValue(pairs) => {
+            check_duplicate_names("record field", pairs.iter().map(|(ident, _)| ident));
+
+            Type::Record(pairs.iter().map(|(ident, expr)| {
+                (ident.name.clone(), infer_type_internal(expr, type_context))
+            }).collect(), None)
+        }
+        ExprKind::RecordExtension(record_expr, pairs) => {
+            check_duplicate_names("record field", pairs.iter().map(|(ident, _)| ident));
+
+            let record_type = infer_type_internal(record_expr, type_context);
+            match expect_record(&record_type, &record_expr.span) {
+                Type::Record(mut fields, rest) => {
+                    for (ident, expr) in pairs {
+                        fields.insert(ident.name.clone(), infer_type_internal(expr, type_context));
+                    }
+                    Type::Record(fields, rest)
+                }
+                _ => Type::Error,
+            }
+        }
+        ExprKind::RecordRemoval(record_expr, removed) => {
+            check_duplicate_names("record field", removed);
+
+            let record_type = infer_type_internal(record_expr, type_context);
+            if let Type::Error = expect_record(&record_type, &record_expr.span) {
+                return Type::Error;
+            }
+
+            // the removed fields have to be there in the first place
+            for ident in removed {
+                if record_field_type(&record_type, &ident.name, &ident.span).is_none() {
+                    type_error!(
+                        ident.span,
+                        "record {} doesn't have a field named {}",
+                        resolve(&record_type), ident,
+                    );
+                }
+            }
+
+            match resolve(&record_type).unfold() {
+                Type::Record(fields, rest) => {
+                    let fields = fields.into_iter()
+                        .filter(|(name, _)| !removed.iter().any(|ident| ident.name == *name))
+                        .collect();
+                    Type::Record(fields, rest)
+                }
+                _ => Type::Error,
+            }
+        }
+        ExprKind::RecordType(pairs) => {
+            check_duplicate_names("record type field", pairs.iter().map(|(ident, _)| ident));
+
+            // each field is in scope in the types of the fields after it,
+            // which is what makes records like `{T: Type, x: T}` possible
+            let mut level = 0;
+            pairs.iter().fold(type_context.clone(), |type_context, (ident, ty_expr)| {
+                level = level.max(expect_type_expr(ty_expr, "record field", &type_context).unwrap_or(0));
+
+                let ty = normalize(ty_expr, &type_context).unwrap_or_else(fresh_var);
+                type_context.extend(ident.name.clone(), TypeBinding::field(ident.name.clone(), ty))
+            });
+
+            Type::Type(level)
+        }
+        ExprKind::RecordFieldAccess(record_expr, field_name) => {
+            let record_type = infer_type_internal(record_expr, type_context);
+
+            match resolve(&record_type).unfold() {
+                Type::Record(..) | Type::Var(_) => {
+                    match record_field_type(&record_type, &field_name.name, &field_name.span) {
+                        // the fields of a module can be polymorphic
+                        Some(field_type) => instantiate(
+                            &expect_independent_field(field_type, field_name, &field_name.span),
+                            &expr.span,
+                            type_context,
+                        ),
+                        None => type_error!(
+                            field_name.span,
+                            "record {} doesn't have a field named {}",
+                            resolve(&record_type), field_name,
+                        ),
+                    }
+                }
+                Type::Error => Type::Error,
+                Type::Optional(_) => type_error!(
+                    record_expr.span,
+                    "expected a record with field `{}`, found optional {}; use `?.{}` or check it with `if let`",
+                    field_name, resolve(&record_type), field_name,
+                ),
+                _ => type_error!(
+                    record_expr.span,
+                    "expected a record with field `{}`, found {}",
+                    field_name, resolve(&record_type),
+                ),
+            }
+        }
+        ExprKind::OptionalRecordFieldAccess(record_expr, field_name) => {
+            let optional_type = infer_type_internal(record_expr, type_context);
+
+            match expect_optional(&optional_type, "?.", &record_expr.span).unfold() {
+                record_type @ Type::Record(..) | record_type @ Type::Var(_) => {
+                    match record_field_type(&record_type, &field_name.name, &field_name.span) {
+                        Some(field_type) => optional(expect_independent_field(field_type, field_name, &field_name.span)),
+                        None => type_error!(
+                            field_name.span,
+                            "record {} doesn't have a field named {}",
+                            resolve(&record_type), field_name,
+                        ),
+                    }
+                }
+                Type::Error => Type::Error,
+                ty => type_error!(
+                    record_expr.span,
+                    "expected an optional record with field `{}`, found {}",
+                    field_name, Type::Optional(Box::new(ty)),
+                ),
+            }
+        }
+        ExprKind::OptionalType(ty_expr) => {
+            Type::Type(expect_type_expr(ty_expr, "optional", type_context).unwrap_or(0))
+        }
+        ExprKind::UnionType(lhs, rhs) => {
+            let lhs_level = expect_type_expr(lhs, "union member", type_context);
+            let rhs_level = expect_type_expr(rhs, "union member", type_context);
+
+            Type::Type(lhs_level.max(rhs_level).unwrap_or(0))
+        }
+        ExprKind::Coalesce(lhs, rhs) => {
+            let lhs_type = infer_type_internal(lhs, type_context);
+            let inner = expect_optional(&lhs_type, "??", &lhs.span);
+            check_type_internal(rhs, &inner, type_context);
+
+            inner
+        }
+        ExprKind::If(condition, then, otherwise) => {
+            check_type_internal(condition, &Type::Bool, type_context);
+
+            let (then_context, otherwise_context) = narrowed_contexts(condition, type_context);
+            let then_type = infer_type_internal(then, &then_context);
+            branches_type(then, then_type, otherwise.as_ref().map(AsRef::as_ref), &otherwise_context)
+        }
+        ExprKind::Is(value, ty_expr) => {
+            let value_type = infer_type_internal(value, type_context);
+            let tested = annotated_type(ty_expr, type_context);
+
+            if let Type::Union(_) = resolve(&value_type).unfold() {
+                if !attempt(|| is_subtype(&tested, &value_type, &ty_expr.span)) {
+                    type_error!(
+                        expr.span,
+                        "a value of type {} is never a {}",
+                        resolve(&value_type), tested,
+                    );
+                }
+            }
+
+            Type::Bool
+        }
+        ExprKind::IfLet(ident, value, then, otherwise) => {
+            let optional_type = infer_type_internal(value, type_context);
+            let inner = expect_optional(&optional_type, "if let", &value.span);
+
+            let then_type = infer_type_internal(then, &type_context.extend(ident.name.clone(), inner.into()));
+            branches_type(then, then_type, otherwise.as_ref().map(AsRef::as_ref), type_context)
+        }
+
+        ExprKind::Block(stmts, expr) => {
+            let type_context = stmts.iter().fold(type_context.clone(), |type_context, stmt| {
+                typeck_stmt_internal(stmt, &type_context)
+            });
+
+            match expr {
                 Some(expr) => infer_type_internal(expr, &type_context),
                 None => Type::Nil,
             }
@@ -190,26 +1559,818 @@ fn infer_type_internal(expr: &Expr, type_context: &TypeContext) -> Type {
 
         ExprKind::Var(ident) => {
             match type_context.lookup(&ident.name) {
-                Some(ty) => ty.clone(),
+                Some(binding) => instantiate(&binding.ty, &expr.span, type_context),
                 None => type_error!(ident.span, "Undeclared variable {}", ident.name),
             }
         }
 
-        ExprKind::Parenthesized(expr) => infer_type_internal(&*expr, type_context),
+        ExprKind::Parenthesized(expr) => infer_type_internal(expr, type_context),
+
+        ExprKind::NilType => Type::Type(0),
+        ExprKind::Universe(level) => Type::Type(level + 1),
+
+        ExprKind::Negate(operand) => {
+            match resolve(&infer_type_internal(operand, type_context)).unfold() {
+                ty @ Type::Int | ty @ Type::Float | ty @ Type::Error => ty,
+                // default to Int if we don't know what kind of number it is
+                ty @ Type::Var(_) => {
+                    unify(&ty, &Type::Int, &operand.span);
+                    Type::Int
+                }
+                ty => type_error!(operand.span, "cannot negate a value of type {}", ty),
+            }
+        }
+
+        ExprKind::BinOp(op, lhs, rhs) => {
+            let lhs_type = infer_type_internal(lhs, type_context);
+            let rhs_type = infer_type_internal(rhs, type_context);
+
+            // if we don't know the type of one operand yet, it has to be the same
+            // as the other one. If we know neither, default to Int (or String for `++`),
+            // except for `+`, which works on any type that implements Add
+            match (resolve(&lhs_type), resolve(&rhs_type)) {
+                (Type::Var(_), Type::Var(_)) if *op == BinOp::Add => {
+                    unify(&lhs_type, &rhs_type, &rhs.span);
+                }
+                (Type::Var(_), Type::Var(_)) => {
+                    let default = match op {
+                        BinOp::Concat => Type::String_,
+                        _ => Type::Int,
+                    };
+                    unify(&lhs_type, &default, &lhs.span);
+                    unify(&rhs_type, &default, &rhs.span);
+                }
+                (Type::Var(_), known) => {
+                    unify(&lhs_type, &known, &lhs.span);
+                }
+                (known, Type::Var(_)) => {
+                    unify(&rhs_type, &known, &rhs.span);
+                }
+                _ => (),
+            }
+
+            match (op, resolve(&lhs_type).unfold(), resolve(&rhs_type).unfold()) {
+                (_, Type::Error, _) | (_, _, Type::Error) => Type::Error,
+                (BinOp::Add, Type::Int, Type::Int)
+                | (BinOp::Sub, Type::Int, Type::Int)
+                | (BinOp::Mul, Type::Int, Type::Int)
+                | (BinOp::Div, Type::Int, Type::Int) => Type::Int,
+                (BinOp::Add, Type::Float, Type::Float)
+                | (BinOp::Sub, Type::Float, Type::Float)
+                | (BinOp::Mul, Type::Float, Type::Float)
+                | (BinOp::Div, Type::Float, Type::Float) => Type::Float,
+                (BinOp::Concat, Type::String_, Type::String_)
+                | (BinOp::Add, Type::String_, Type::String_) => Type::String_,
+                (BinOp::Add, _, _) if unify(&lhs_type, &rhs_type, &rhs.span) => {
+                    require(operator_trait(*op).0.into(), lhs_type.clone(), &expr.span, type_context);
+                    lhs_type
+                }
+                (op, _, _) => conflict_error(
+                    &expr.span,
+                    format!("cannot apply `{}` to {} and {}", op, resolve(&lhs_type), resolve(&rhs_type)),
+                    &[&lhs_type, &rhs_type],
+                ),
+            }
+        }
+
+        ExprKind::Coerce(expr, ty) => {
+            check_type_internal(expr, ty, type_context);
+            ty.clone()
+        }
+
+        ExprKind::Lambda(params, body) => {
+            check_duplicate_names("parameter", params);
+
+            let param_types: Vec<Type> = params.iter().map(|_| fresh_var()).collect();
+            let body_context = params.iter().zip(&param_types)
+                .fold(type_context.clone(), |type_context, (param, ty)| {
+                    type_context.extend(param.name.clone(), ty.clone().into())
+                });
+            let return_type = infer_type_internal(body, &body_context);
+
+            Type::Function(param_types, Box::new(return_type))
+        }
+
+        ExprKind::Call(function, args) => {
+            let function_type = infer_type_internal(function, type_context);
+
+            // only resolve the outside, so that errors about the parameters
+            // can still say where their types were inferred
+            let function_type_head = resolve_head(&function_type).unfold();
+
+            // when the parameter types are known, the arguments are checked against
+            // them one by one, so that e.g. the list passed to `map` decides the type
+            // of the lambda's parameter
+            if let Type::Function(param_types, return_type) = &function_type_head {
+                if param_types.len() != args.len() {
+                    args.iter().for_each(|arg| { infer_type_internal(arg, type_context); });
+                    return type_error!(
+                        expr.span,
+                        "expected {} arguments, found {}",
+                        param_types.len(), args.len(),
+                    );
+                }
+
+                // in a dependent function type like `(String, Type) -> .1`, the types
+                // of later parameters and the return type can depend on earlier arguments
+                let mut types: Vec<Type> = param_types.iter().cloned()
+                    .chain(iter::once((**return_type).clone()))
+                    .collect();
+
+                for (number, arg) in args.iter().enumerate() {
+                    check_type_internal(arg, &types[number], type_context);
+                    substitute_later_fields(&mut types[number + 1..], &number.to_string().into(), arg, type_context);
+                }
+
+                return types.pop().unwrap();
+            }
+
+            let arg_types: Vec<Type> = args.iter()
+                .map(|arg| infer_type_internal(arg, type_context))
+                .collect();
+
+            match function_type_head {
+                Type::Var(_) => {
+                    let param_types: Vec<Type> = args.iter().map(|_| fresh_var()).collect();
+                    let return_type = fresh_var();
+                    unify(
+                        &function_type,
+                        &Type::Function(param_types.clone(), Box::new(return_type.clone())),
+                        &function.span,
+                    );
+
+                    for ((param_type, arg_type), arg) in param_types.iter().zip(arg_types).zip(args) {
+                        expect_type(param_type, &arg_type, &arg.span);
+                    }
+
+                    return_type
+                }
+                Type::Error => Type::Error,
+                ty => type_error!(function.span, "expected a function, found a {}", resolve(&ty)),
+            }
+        }
+    }
+}
+
+/// Checks that `expr` has type `expected`, pushing the expected type down into
+/// record and tuple fields, collection elements, lambda bodies and the ends of
+/// blocks and branches. Anything else has its type inferred and compared.
+fn check_type_internal(expr: &Expr, expected: &Type, type_context: &TypeContext) {
+    match (&expr.kind, resolve_head(expected).unfold()) {
+        (ExprKind::Parenthesized(inner), _) => check_type_internal(inner, expected, type_context),
+
+        (ExprKind::Tuple(elements), Type::Tuple(mut types)) if elements.len() == types.len() => {
+            for (number, element) in elements.iter().enumerate() {
+                check_type_internal(element, &types[number], type_context);
+                substitute_later_fields(&mut types[number + 1..], &number.to_string().into(), element, type_context);
+            }
+        }
+        (ExprKind::RecordValue(pairs), Type::Record(field_types, None)) => {
+            check_duplicate_names("record field", pairs.iter().map(|(ident, _)| ident));
+
+            for (ident, value) in pairs {
+                if !field_types.contains_key(&ident.name) {
+                    infer_type_internal(value, type_context);
+                    type_error!(
+                        ident.span,
+                        "unexpected field `{}` in record of type {}",
+                        ident, resolve(expected),
+                    );
+                }
+            }
+
+            // the fields are checked in the order of the type, because the types
+            // of later fields can depend on the values of earlier ones
+            let (names, mut types): (Vec<Name>, Vec<Type>) = field_types.into_iter().unzip();
+            for (index, name) in names.iter().enumerate() {
+                match pairs.iter().find(|(ident, _)| ident.name == *name) {
+                    Some((_, value)) => {
+                        check_type_internal(value, &types[index], type_context);
+                        substitute_later_fields(&mut types[index + 1..], name, value, type_context);
+                    }
+                    None => {
+                        type_error!(expr.span, "missing field `{}` of type {}", name, resolve(&types[index]));
+                    }
+                }
+            }
+        }
+
+        // a string literal has the literal type if that's what's expected, but it's
+        // inferred to be a String otherwise, since that's almost always what's wanted
+        (ExprKind::StringLiteral(s), Type::Literal(literal)) if *s == literal => (),
+        (ExprKind::StringLiteral(s), Type::Union(members)) if members.contains(&Type::Literal(s.clone())) => {
+            record_coercion(&expr.span, expected);
+        }
+
+        (ExprKind::List(elements), Type::List(element_type))
+        | (ExprKind::Set(elements), Type::Set(element_type)) => {
+            for element in elements {
+                check_type_internal(element, &element_type, type_context);
+            }
+        }
+        (ExprKind::Map(entries), Type::Map(key_type, value_type)) => {
+            check_duplicate_keys(entries);
+            for (key, value) in entries {
+                check_type_internal(key, &key_type, type_context);
+                check_type_internal(value, &value_type, type_context);
+            }
+        }
+
+        (ExprKind::Lambda(params, body), Type::Function(param_types, return_type))
+            if params.len() == param_types.len() =>
+        {
+            check_duplicate_names("parameter", params);
+
+            let body_context = params.iter().zip(param_types)
+                .fold(type_context.clone(), |type_context, (param, ty)| {
+                    type_context.extend(param.name.clone(), ty.into())
+                });
+            check_type_internal(body, &return_type, &body_context);
+        }
+
+        (ExprKind::Block(stmts, Some(result)), _) => {
+            let type_context = stmts.iter().fold(type_context.clone(), |type_context, stmt| {
+                typeck_stmt_internal(stmt, &type_context)
+            });
+
+            check_type_internal(result, expected, &type_context);
+        }
+        (ExprKind::If(condition, then, Some(otherwise)), _) => {
+            check_type_internal(condition, &Type::Bool, type_context);
+
+            let (then_context, otherwise_context) = narrowed_contexts(condition, type_context);
+            check_type_internal(then, expected, &then_context);
+            check_type_internal(otherwise, expected, &otherwise_context);
+        }
+        (ExprKind::IfLet(ident, value, then, Some(otherwise)), _) => {
+            let optional_type = infer_type_internal(value, type_context);
+            let inner = expect_optional(&optional_type, "if let", &value.span);
+
+            check_type_internal(then, expected, &type_context.extend(ident.name.clone(), inner.into()));
+            check_type_internal(otherwise, expected, type_context);
+        }
+
+        _ => {
+            let found = infer_type_internal(expr, type_context);
+            expect_type(expected, &found, &expr.span);
+        }
+    }
+}
+
+/// In a dependent record, tuple or function type, replaces references to the field
+/// or parameter `name` in the types after it with the type that `value` is. Emits
+/// an error if they refer to it, but it can't be worked out before the program runs.
+fn substitute_later_fields(later_types: &mut [Type], name: &Name, value: &Expr, type_context: &TypeContext) {
+    if !later_types.iter().any(|ty| field_refs(ty).contains(name)) {
+        return;
+    }
+
+    let value_type = normalize(value, type_context).unwrap_or_else(|| {
+        type_error!(
+            value.span,
+            "the types after this depend on its value, so it has to be a type that's known before the program runs",
+        )
+    });
+
+    for ty in later_types {
+        *ty = substitute_field(ty, name, &value_type);
+    }
+}
+
+/// The type of the field called `name` in records of type `record_type`, or `None`
+/// if they can't have one. If some of the fields aren't known yet, the field is
+/// added to the ones that the record has to have.
+fn record_field_type(record_type: &Type, name: &Name, span: &Span) -> Option<Type> {
+    let require_field = |rest: &Type| {
+        let field_type = fresh_var();
+        let fields = iter::once((name.clone(), field_type.clone())).collect();
+
+        if unify(rest, &Type::Record(fields, Some(Box::new(fresh_var()))), span) {
+            Some(field_type)
+        } else {
+            None
+        }
+    };
+
+    match resolve(record_type).unfold() {
+        Type::Record(fields, rest) => match fields.get(name) {
+            Some(field_type) => Some(field_type.clone()),
+            None => rest.and_then(|rest| require_field(&rest)),
+        },
+        var @ Type::Var(_) => require_field(&var),
+        _ => None,
+    }
+}
+
+/// The type of element `number` of tuples of type `tuple_type`, or an error if
+/// they don't have one. If it isn't known how many elements there are yet,
+/// the tuple has to have at least that many.
+fn tuple_element_type(tuple_type: &Type, number: usize, tuple_span: &Span, span: &Span) -> Type {
+    let require_element = |types: &[Type], rest: &Type| {
+        let missing: Vec<Type> = (types.len()..=number).map(|_| fresh_var()).collect();
+        let element_type = missing[missing.len() - 1].clone();
+
+        if unify(rest, &Type::OpenTuple(missing, Box::new(fresh_var())), span) {
+            element_type
+        } else {
+            type_error!(tuple_span, "expected a tuple with at least {} elements, found {}", number + 1, resolve(tuple_type))
+        }
+    };
+
+    match resolve(tuple_type).unfold() {
+        Type::Tuple(types) => match types.get(number) {
+            Some(element_type) => expect_independent_field(element_type.clone(), &number, span),
+            None => type_error!(span, "field number {} is out of range for tuple {}", number, resolve(tuple_type)),
+        },
+        Type::OpenTuple(types, rest) => match types.get(number) {
+            Some(element_type) => element_type.clone(),
+            None => require_element(&types, &rest),
+        },
+        var @ Type::Var(_) => require_element(&[], &var),
+        Type::Error => Type::Error,
+        _ => type_error!(
+            tuple_span,
+            "expected a tuple with at least {} elements, found {}",
+            number + 1, resolve(tuple_type),
+        ),
+    }
+}
+
+/// Returns `ty` as a record type, or emits an error if it isn't a record.
+/// If `ty` isn't known yet, it becomes a record with unknown fields.
+fn expect_record(ty: &Type, span: &Span) -> Type {
+    match resolve(ty).unfold() {
+        record_type @ Type::Record(..) => record_type,
+        var @ Type::Var(_) => {
+            let record_type = Type::Record(OrderedMap::default(), Some(Box::new(fresh_var())));
+            unify(&var, &record_type, span);
+            record_type
+        }
+        Type::Error => Type::Error,
+        _ => type_error!(span, "expected a record, found {}", resolve(ty)),
+    }
+}
+
+/// Returns the type inside `ty`, or emits an error if it isn't optional.
+/// `what` is the syntax that needs an optional, e.g. `??`.
+fn expect_optional(ty: &Type, what: &str, span: &Span) -> Type {
+    match resolve(ty).unfold() {
+        Type::Optional(inner) => *inner,
+        Type::Error => Type::Error,
+        var @ Type::Var(_) => {
+            let inner = fresh_var();
+            unify(&var, &optional(inner.clone()), span);
+            inner
+        }
+        _ => type_error!(span, "`{}` needs an optional, found {}", what, resolve(ty)),
+    }
+}
+
+/// `T?`, except that `T` is returned as is if it is already optional, so that
+/// `a?.b?.c` doesn't need to unwrap twice
+fn optional(ty: Type) -> Type {
+    match resolve(&ty).unfold() {
+        Type::Optional(_) => ty,
+        _ => Type::Optional(Box::new(ty)),
+    }
+}
+
+/// The contexts to check the branches of an `if` in. When the condition is
+/// `x is T` and `x` is a union, `x` is a `T` in the first branch, and one of the
+/// union's other members in the second.
+fn narrowed_contexts(condition: &Expr, type_context: &TypeContext) -> (TypeContext, TypeContext) {
+    if let ExprKind::Is(value, ty_expr) = &condition.kind {
+        if let (ExprKind::Var(ident), Some(tested)) = (&value.kind, normalize(ty_expr, type_context)) {
+            // a mutable variable isn't narrowed, since it could be assigned a value
+            // of another member of the union in the branch, e.g. by a function it calls
+            let value_type = type_context.lookup(&ident.name)
+                .filter(|binding| !binding.mutable)
+                .map(|binding| resolve(&binding.ty).unfold());
+
+            if let Some(Type::Union(members)) = value_type {
+                let rest: Vec<Type> = members.iter()
+                    .filter(|member| !attempt(|| is_subtype(member, &tested, &condition.span)))
+                    .cloned()
+                    .collect();
+                // if every member passes the test, the second branch can't happen
+                let rest = if rest.is_empty() { Type::Union(members) } else { Type::union(rest) };
+
+                return (
+                    type_context.extend(ident.name.clone(), tested.into()),
+                    type_context.extend(ident.name.clone(), rest.into()),
+                );
+            }
+        }
+    }
+
+    (type_context.clone(), type_context.clone())
+}
+
+/// The type of an `if` whose first branch has type `then_type`. Without
+/// an `else`, the first branch has to be nil.
+fn branches_type(then: &Expr, then_type: Type, otherwise: Option<&Expr>, type_context: &TypeContext) -> Type {
+    match otherwise {
+        Some(otherwise) => {
+            let otherwise_type = infer_type_internal(otherwise, type_context);
+            if unify(&then_type, &otherwise_type, &otherwise.span) {
+                then_type
+            } else {
+                conflict_error(
+                    &otherwise.span,
+                    format!(
+                        "`if` and `else` have different types: {} and {}",
+                        resolve(&then_type), resolve(&otherwise_type),
+                    ),
+                    &[&then_type, &otherwise_type],
+                )
+            }
+        }
+        None => {
+            expect_type(&Type::Nil, &then_type, &then.span);
+            Type::Nil
+        }
+    }
+}
+
+/// Emits an error if `found` can't be unified with `expected`. Error types are
+/// compatible with everything so that one mistake isn't reported twice.
+fn expect_type(expected: &Type, found: &Type, span: &Span) {
+    // a type in one universe is in all the ones above it too
+    if let (Type::Type(expected_level), Type::Type(found_level)) = (resolve_head(expected), resolve_head(found)) {
+        if found_level > expected_level {
+            conflict_error(span, format!(
+                "expected a type in {}, found one in {}",
+                Type::Type(expected_level), Type::Type(found_level),
+            ), &[expected, found]);
+        }
+        return;
+    }
+
+    if attempt(|| unify(expected, found, span)) {
+        return;
+    }
 
-        ExprKind::NilType => Type::Type,
+    // a member of a union can be used wherever the union is expected,
+    // and so can a list of them where a list of the union is expected
+    if attempt(|| is_subtype(found, expected, span)) {
+        record_coercion(span, expected);
+        return;
     }
+
+    let (expected_type, found_type) = (resolve(expected), resolve(found));
+
+    let message = match (&expected_type, &found_type) {
+        (Type::Var(var), ty) | (ty, Type::Var(var)) if free_vars(ty).contains(var) => {
+            format!("infinite type: {} would have to contain itself in {}", var, ty)
+        }
+        _ => format!("expected {}, found {}", expected_type, found_type),
+    };
+
+    conflict_error(span, message, &[expected, found]);
+}
+
+/// Emits an error about types that don't fit together, with notes showing
+/// where those types were inferred
+fn conflict_error(span: &Span, message: String, types: &[&Type]) -> Type {
+    TypeError {
+        message,
+        span: span.clone(),
+        notes: inference_notes(types),
+    }.emit();
+
+    Type::Error
 }
 
 fn typeck_stmt_internal(stmt: &Stmt, type_context: &TypeContext) -> TypeContext {
     match &stmt.kind {
-        StmtKind::Let(ident, expr) => {
-            let ty = infer_type_internal(expr, &type_context);
-            type_context.extend(ident.name.clone(), ty)
+        StmtKind::Let(ident, annotation, expr) => {
+            let ty = match annotation {
+                Some(annotation) => {
+                    let ty = annotated_type(annotation, type_context);
+                    check_type_internal(expr, &ty, type_context);
+                    ty
+                }
+                None => infer_type_internal(expr, type_context),
+            };
+
+            let binding = TypeBinding {
+                ty: generalize(&ty, type_context, expr),
+                value: evaluate_statically(expr, type_context),
+                mutable: false,
+            };
+            type_context.extend(ident.name.clone(), binding)
+        }
+        StmtKind::LetMut(ident, annotation, expr) => {
+            // the variable isn't generalized, since a value assigned to it later
+            // has to have the type it was first used at
+            let ty = match annotation {
+                Some(annotation) => {
+                    let ty = annotated_type(annotation, type_context);
+                    check_type_internal(expr, &ty, type_context);
+                    ty
+                }
+                None => infer_type_internal(expr, type_context),
+            };
+
+            type_context.extend(ident.name.clone(), TypeBinding::mutable(ty))
+        }
+        StmtKind::Assign(ident, expr) => {
+            match type_context.lookup(&ident.name) {
+                Some(binding) if binding.mutable => check_type_internal(expr, &binding.ty, type_context),
+                Some(_) => {
+                    type_error!(ident.span, "can't assign to `{}`, which wasn't declared with `let mut`", ident.name);
+                }
+                None => {
+                    type_error!(ident.span, "Undeclared variable {}", ident.name);
+                }
+            }
+
+            type_context.clone()
+        }
+        StmtKind::Type(ident, ty_expr) => {
+            // inside its own definition, the name refers to the type being defined
+            let name = ident.name.clone();
+            let recursive = Value::Type(Type::Recursive(name.clone()));
+            let inner_context = type_context.extend(name.clone(), TypeBinding::for_value(Type::Type(0), &recursive));
+
+            let binding = match annotated_type(ty_expr, &inner_context) {
+                Type::Error => Type::Error.into(),
+                Type::Recursive(_) => {
+                    type_error!(ty_expr.span, "type {} can't be defined as itself", name);
+                    Type::Error.into()
+                }
+                definition => {
+                    let ty = Type::Named(name.clone(), Box::new(definition));
+                    if ty.is_recursive() && ty.level() > 0 {
+                        type_error!(
+                            ty_expr.span,
+                            "recursive type {} has to be in Type, but it's in {}",
+                            name, Type::Type(ty.level()),
+                        );
+                    }
+                    TypeBinding::for_value(Type::Type(ty.level()), &Value::Type(ty))
+                }
+            };
+            type_context.extend(name, binding)
+        }
+        StmtKind::Trait(ident, methods) => {
+            check_duplicate_names("method", methods.iter().map(|(name, _, _)| name));
+
+            // `Self` is the implementing type in the types of the methods
+            let self_type = Value::Type(Type::Param("Self".into()));
+            let inner_context = type_context.extend("Self".into(), TypeBinding::for_value(Type::Type(0), &self_type));
+
+            let method_types: OrderedMap<Name, Type> = methods.iter()
+                .map(|(name, params, ret)| {
+                    let params = params.iter().map(|param| annotated_type(param, &inner_context)).collect();
+                    let ret = annotated_type(ret, &inner_context);
+                    (name.name.clone(), Type::Function(params, Box::new(ret)))
+                })
+                .collect();
+
+            let trait_name = &ident.name;
+            let trait_binding = TypeBinding::from(Type::Record(method_types.clone(), None));
+            method_types.into_iter().fold(
+                type_context.extend(trait_binding_name(trait_name), trait_binding),
+                |type_context, (name, ty)| type_context.extend(name, Type::method(trait_name, ty).into()),
+            )
+        }
+        StmtKind::Impl(trait_ident, ty_expr, methods) => {
+            let trait_name = &trait_ident.name;
+            let trait_type = match type_context.lookup(&trait_binding_name(trait_name)) {
+                Some(binding) => binding.ty.clone(),
+                None => {
+                    type_error!(trait_ident.span, "Undeclared trait {}", trait_name);
+                    return type_context.clone();
+                }
+            };
+            let ty = annotated_type(ty_expr, type_context);
+
+            check_duplicate_names("method", methods.iter().map(|(name, _)| name));
+
+            let dictionary_type = dictionary_type(&trait_type, &ty);
+            let method_types = match &dictionary_type {
+                Type::Record(method_types, None) => method_types,
+                ty => unreachable!("trait {} should be a record of its methods, not {}", trait_name, ty),
+            };
+
+            for (name, expr) in methods {
+                match method_types.get(&name.name) {
+                    Some(method_type) => check_type_internal(expr, method_type, type_context),
+                    None => {
+                        type_error!(name.span, "`{}` isn't a method of {}", name.name, trait_name);
+                    }
+                }
+            }
+            for name in method_types.keys() {
+                if !methods.iter().any(|(method, _)| method.name == *name) {
+                    type_error!(stmt.span, "the implementation of {} for {} is missing `{}`", trait_name, ty, name);
+                }
+            }
+
+            if ty == Type::Error {
+                return type_context.clone();
+            }
+            type_context.extend(dictionary_name(trait_name, &ty), dictionary_type.into())
+        }
+        // loading files is up to the interpreter, which does it for the imports it's given
+        StmtKind::Import(..) => {
+            type_error!(stmt.span, "modules can only be imported at the top level of a file");
+            type_context.clone()
+        }
+    }
+}
+
+pub fn typeck_stmt(
+    stmt: &Stmt,
+    type_context: &TypeContext,
+    substitution: &mut Substitution,
+) -> Result<TypeContext, Vec<TypeError>> {
+    elaborate_stmt(stmt, type_context, substitution).map(|(_, type_context)| type_context)
+}
+
+/// `elaborate` for statements
+pub fn elaborate_stmt(
+    stmt: &Stmt,
+    type_context: &TypeContext,
+    substitution: &mut Substitution,
+) -> Result<(Stmt, TypeContext), Vec<TypeError>> {
+    collect_type_errors(substitution, || {
+        let type_context = typeck_stmt_internal(stmt, type_context);
+        let mut elaboration = finish();
+        (stmt.map(|expr| elaborate_expr(expr, &mut elaboration)), type_context)
+    })
+}
+
+/// Remembers that the value of the expression at `span` is used as a value of
+/// the union type `ty`, so that `elaborate` can make that explicit
+fn record_coercion(span: &Span, ty: &Type) {
+    ERROR_CONTEXT.with(|error_context| {
+        let mut error_context = error_context.borrow_mut();
+        assert!(error_context.in_use);
+
+        let coercions = &mut error_context.elaboration.coercions;
+        if !coercions.iter().any(|(other, _)| other == span) {
+            coercions.push((span.clone(), ty.clone()));
+        }
+    })
+}
+
+/// Remembers that the expression at `span` needs an implementation of
+/// `trait_name` for `ty`, which is looked for once the types are all known
+fn require(trait_name: Name, ty: Type, span: &Span, type_context: &TypeContext) {
+    ERROR_CONTEXT.with(|error_context| {
+        let mut error_context = error_context.borrow_mut();
+        assert!(error_context.in_use);
+
+        error_context.obligations.push(Obligation {
+            trait_name,
+            ty,
+            span: span.clone(),
+            type_context: type_context.clone(),
+            dictionary: None,
+        });
+    })
+}
+
+/// Finds the implementations for the obligations that weren't turned into
+/// constraints, and returns everything that elaborating the program needs
+fn finish() -> Elaboration {
+    let obligations = ERROR_CONTEXT.with(|error_context| mem::take(&mut error_context.borrow_mut().obligations));
+
+    let mut dictionaries = Vec::new();
+    for obligation in obligations {
+        let dictionary = match obligation.dictionary {
+            Some(dictionary) => dictionary,
+            None => match find_implementation(&obligation) {
+                Some(dictionary) => dictionary,
+                None => continue,
+            },
+        };
+        dictionaries.push((obligation.span, dictionary));
+    }
+
+    ERROR_CONTEXT.with(|error_context| {
+        let mut elaboration = mem::take(&mut error_context.borrow_mut().elaboration);
+        elaboration.dictionaries = dictionaries;
+        elaboration
+    })
+}
+
+/// The name of the dictionary of the implementation that `obligation` needs.
+/// Types without an implementation of their own use the trait's default
+/// implementation, if it has one.
+fn find_implementation(obligation: &Obligation) -> Option<Name> {
+    let ty = resolve(&obligation.ty);
+    if ty == Type::Error {
+        return None;
+    }
+
+    let own = dictionary_name(&obligation.trait_name, &ty);
+    let default = default_dictionary_name(&obligation.trait_name);
+
+    if obligation.type_context.lookup(&own).is_some() {
+        Some(own)
+    } else if obligation.type_context.lookup(&default).is_some() {
+        Some(default)
+    } else if free_vars(&ty).is_empty() {
+        type_error!(obligation.span, "no implementation of {} for {}", obligation.trait_name, ty);
+        None
+    } else {
+        // the type is often unknown because of an earlier mistake, which has already been reported
+        let has_errors = ERROR_CONTEXT.with(|error_context| !error_context.borrow().errors.is_empty());
+        if !has_errors {
+            type_error!(obligation.span, "can't work out which implementation of {} is needed for {}", obligation.trait_name, ty);
         }
+        None
     }
 }
 
-pub fn typeck_stmt(stmt: &Stmt, type_context: &TypeContext) -> Result<TypeContext, Vec<TypeError>> {
-    collect_type_errors(|| typeck_stmt_internal(stmt, type_context))
+/// The variable that the trait `trait_name` is declared in. Like the other
+/// names made up by the type checker, it can't be written in a program.
+pub fn trait_binding_name(trait_name: &Name) -> Name {
+    format!("trait {}", trait_name).into()
+}
+
+/// The variable holding the dictionary of the implementation of `trait_name`
+/// for `ty`, which is a record of its methods, e.g. `Show[Int]`
+pub fn dictionary_name(trait_name: &Name, ty: &Type) -> Name {
+    format!("{}[{}]", trait_name, ty).into()
+}
+
+/// Whether `name` is one of the variables that hold dictionaries
+pub fn is_dictionary_name(name: &Name) -> bool {
+    name.as_ref().ends_with(']')
+}
+
+/// The dictionary of the implementation that a trait has for every type, if it has one
+pub fn default_dictionary_name(trait_name: &Name) -> Name {
+    format!("{}[_]", trait_name).into()
+}
+
+/// The type of the dictionary of an implementation of a trait for `ty`, given
+/// the type of the trait's binding
+pub fn dictionary_type(trait_type: &Type, ty: &Type) -> Type {
+    let params = iter::once(("Self".into(), ty.clone())).collect();
+    substitute_params(trait_type, &params)
+}
+
+/// Makes what `elaboration` has for the expressions in `expr` explicit
+fn elaborate_expr(expr: &Expr, elaboration: &mut Elaboration) -> Expr {
+    let mut elaborated = expr.map(|expr| elaborate_expr(expr, elaboration));
+    let at_span = |kind| Expr {kind, span: expr.span.clone()};
+
+    let mut dictionaries = Vec::new();
+    elaboration.dictionaries.retain(|(span, dictionary)| {
+        let matches = *span == expr.span;
+        if matches {
+            dictionaries.push(at_span(ExprKind::Var(Ident {name: dictionary.clone(), span: span.clone()})));
+        }
+        !matches
+    });
+
+    if !dictionaries.is_empty() {
+        elaborated = match elaborated.kind {
+            // operators call the method of their trait
+            ExprKind::BinOp(op, lhs, rhs) => {
+                let method = Ident {name: operator_trait(op).1.into(), span: expr.span.clone()};
+                let method = at_span(ExprKind::RecordFieldAccess(Box::new(dictionaries.remove(0)), method));
+                at_span(ExprKind::Call(Box::new(method), vec![*lhs, *rhs]))
+            }
+            ExprKind::Interpolated(parts) => at_span(ExprKind::Interpolated(parts.into_iter()
+                .map(|part| match part {
+                    StringPart::Expr(part) => {
+                        let span = part.span.clone();
+                        let method = Ident {name: "to_string".into(), span: span.clone()};
+                        let method = ExprKind::RecordFieldAccess(Box::new(dictionaries.remove(0)), method);
+                        let method = Expr {kind: method, span: span.clone()};
+                        StringPart::Expr(Expr {kind: ExprKind::Call(Box::new(method), vec![part]), span})
+                    }
+                    literal => literal,
+                })
+                .collect())),
+            kind => at_span(ExprKind::Call(Box::new(at_span(kind)), dictionaries)),
+        };
+    }
+
+    if let Some(index) = elaboration.coercions.iter().position(|(span, _)| *span == expr.span) {
+        let (_, ty) = elaboration.coercions.remove(index);
+        elaborated = at_span(ExprKind::Coerce(Box::new(elaborated), resolve(&ty)));
+    }
+
+    if let Some(index) = elaboration.dictionary_params.iter().position(|(span, _)| *span == expr.span) {
+        let (span, params) = elaboration.dictionary_params.remove(index);
+        let params = params.into_iter().map(|name| Ident {name, span: span.clone()}).collect();
+        elaborated = at_span(ExprKind::Lambda(params, Box::new(elaborated)));
+    }
+
+    elaborated
+}
+
+/// The trait that an overloaded operator uses, and its method
+fn operator_trait(op: BinOp) -> (&'static str, &'static str) {
+    match op {
+        BinOp::Add => ("Add", "plus"),
+        op => unreachable!("`{}` isn't overloaded", op),
+    }
 }
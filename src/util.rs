@@ -3,45 +3,159 @@ use {
     std::fmt::{self, Display},
 };
 
-pub fn unescape(s: &str) -> String {
-    let mut escaping = false;
+/// The opposite of `unescape`: how `s` is written inside a string literal
+pub fn escape(s: &str) -> String {
     let mut output = String::new();
 
     for c in s.chars() {
-        if !escaping && c == '\\' {
-            escaping = true;
+        match c {
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '\0' => output.push_str("\\0"),
+            '\\' | '"' | '$' => {
+                output.push('\\');
+                output.push(c);
+            }
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+pub fn unescape(s: &str) -> Result<String, String> {
+    let mut output = String::new();
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
             continue
         }
 
-        let escaped = if escaping {
-            match c {
-                'n' => '\n',
-                'r' => '\r',
-                't' => '\t',
-                _ => c,
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('$') => '$',
+            Some('u') => {
+                let rest = chars.as_str();
+
+                let digits = match (rest.starts_with('{'), rest.find('}')) {
+                    (true, Some(end)) => &rest[1..end],
+                    _ => return Err("expected `{` and `}` around the code point in `\\u` escape".into()),
+                };
+
+                let c = if digits.len() <= 6 && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                    u32::from_str_radix(digits, 16).ok().and_then(std::char::from_u32)
+                } else {
+                    None
+                };
+
+                match c {
+                    Some(c) => {
+                        // skip over the `{...}`
+                        chars = rest[digits.len() + 2..].chars();
+                        c
+                    }
+                    None => return Err(format!("invalid unicode escape `\\u{{{}}}`", digits)),
+                }
             }
-        } else {
-            c
+            Some(c) => return Err(format!("invalid escape sequence `\\{}`", c)),
+            None => return Err("string ends with a backslash".into()),
         };
 
         output.push(escaped);
-        escaping = false;
-        continue
     }
 
-    output
+    Ok(output)
 }
 
-pub fn join<I: Iterator<Item=impl Display> + Clone>(
+pub enum RawStringPart<'a> {
+    /// Literal text, still containing escape sequences
+    Literal(&'a str),
+    /// The source of an interpolated expression, and its byte offset in the string
+    Expr(usize, &'a str),
+}
+
+/// Splits the contents of a string literal into literal text and `${...}` interpolations.
+/// Interpolated expressions end at the `}` that matches the `${`, skipping over braces
+/// in string literals.
+pub fn split_interpolations(s: &str) -> Result<Vec<RawStringPart<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut literal_start = 0;
+    // all the characters we care about are ASCII, so it's fine to go byte by byte
+    let bytes = s.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                let expr_start = i + 2;
+                let expr_end = match matching_brace(&bytes[expr_start..]) {
+                    Some(len) => expr_start + len,
+                    None => return Err("unterminated `${` in string literal".into()),
+                };
+
+                if literal_start < i {
+                    parts.push(RawStringPart::Literal(&s[literal_start..i]));
+                }
+                parts.push(RawStringPart::Expr(expr_start, &s[expr_start..expr_end]));
+
+                i = expr_end + 1;
+                literal_start = i;
+            }
+            _ => i += 1,
+        }
+    }
+
+    if literal_start < s.len() {
+        parts.push(RawStringPart::Literal(&s[literal_start..]));
+    }
+
+    Ok(parts)
+}
+
+/// The index of the `}` that closes a block starting just before `bytes`
+fn matching_brace(bytes: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            b'"' => {
+                // skip to the end of the string literal
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    None
+}
+
+pub fn join<I: IntoIterator<Item=impl Display> + Clone>(
     joiner: &'static str,
     it: I,
 ) -> impl Display {
     struct Join<I>(&'static str, I);
 
-    impl<I: Iterator<Item=impl Display> + Clone> Display for Join<I> {
+    impl<I: IntoIterator<Item=impl Display> + Clone> Display for Join<I> {
         fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
             let Join(joiner, it) = self;
-            let mut it: I = it.clone();
+            let mut it = it.clone().into_iter();
 
             match it.next() {
                 Some(d) => write!(fmt, "{}", d)?,
@@ -74,3 +188,10 @@ pub fn mapping<K: Display, V: Display>(
 }
 
 pub type Map<K, V> = fnv::FnvHashMap<K, V>;
+
+/// A map that remembers the order its keys were inserted in, used for record fields
+/// so they are displayed in declaration order. Equality ignores the order.
+pub type OrderedMap<K, V> = indexmap::IndexMap<K, V, fnv::FnvBuildHasher>;
+
+/// The set version of `OrderedMap`
+pub type OrderedSet<T> = indexmap::IndexSet<T, fnv::FnvBuildHasher>;
@@ -5,17 +5,31 @@ use {
             ExprKind,
             Stmt,
             StmtKind,
+            BinOp,
+            StringPart,
             Name,
-            Number,
         },
-        typeck::Type,
+        json::substitute_later_fields,
+        typeck::{Type, dictionary_name, is_dictionary_name},
         util::{
-            Map,
+            OrderedMap,
+            OrderedSet,
+            escape,
             join,
             mapping,
         }
     },
     derive_more::Display,
+    fnv::FnvHasher,
+    im::Vector,
+    num_bigint::BigInt,
+    num_traits::Zero,
+    std::{
+        fmt,
+        hash::{Hash, Hasher},
+        mem,
+        sync::{Arc, Mutex},
+    },
 };
 
 pub use crate::context::ValueContext;
@@ -24,29 +38,336 @@ pub use crate::context::ValueContext;
 #[display(fmt = "VmError: {}", _0)]
 pub struct VmError(String);
 
+impl VmError {
+    pub fn new(message: impl Into<String>) -> Self {
+        VmError(message.into())
+    }
+}
+
 macro_rules! type_error {
     ($($tt:tt)*) => {
         return Err(VmError(format!($($tt)*)))
     };
 }
 
-#[derive(Debug, Display, Clone, Eq, PartialEq)]
+/// Any value can be a map key or set element, so values implement `Eq` and `Hash`.
+/// Floats are compared by their bits for this, so `nan` is equal to itself and
+/// can be found again after it is inserted.
+#[derive(Debug, Display, Clone)]
 pub enum Value {
     #[display(fmt = "nil")]
     Nil,
     #[display(fmt = "{{{}}}", r#"join(", ", _0.iter().map(mapping("=")))"#)]
-    Record(Map<Name, Value>),
+    Record(OrderedMap<Name, Value>),
     #[display(fmt = "({})", r#"join(", ", _0.iter())"#)]
     Tuple(Vec<Value>),
+    #[display(fmt = "[{}]", r#"join(", ", _0)"#)]
+    List(Vector<Value>),
+    #[display(fmt = "{}", r#"display_map(_0)"#)]
+    Map(OrderedMap<Value, Value>),
+    #[display(fmt = "#[{}]", r#"join(", ", _0)"#)]
+    Set(OrderedSet<Value>),
     #[display(fmt = "{}", _0)]
-    Number(Number),
+    Int(BigInt),
+    // Debug formatting always includes a decimal point or exponent,
+    // so floats can be told apart from ints
     #[display(fmt = "{:?}", _0)]
+    Float(f64),
+    #[display(fmt = "\"{}\"", r#"escape(_0)"#)]
     String_(String),
     #[display(fmt = "{}", _0)]
+    Bool(bool),
+    #[display(fmt = "{}", _0)]
     Type(Type),
+    #[display(fmt = "{}", _0)]
+    Builtin(Builtin),
+    #[display(fmt = "{}", _0)]
+    Closure(Closure),
+    #[display(fmt = "{}", r#"display_optional(_0)"#)]
+    Optional(Option<Box<Value>>),
+    /// Where a `let mut` variable's value is kept in a `ValueContext`. Reading
+    /// the variable gets the value out, so programs never see a cell.
+    #[display(fmt = "{}", _0)]
+    Cell(Cell),
+}
+
+fn display_optional(optional: &Option<Box<Value>>) -> String {
+    match optional {
+        Some(value) => format!("some({})", value),
+        None => "none".to_string(),
+    }
+}
+
+fn display_map(map: &OrderedMap<Value, Value>) -> String {
+    if map.is_empty() {
+        "[:]".to_string()
+    } else {
+        format!("[{}]", join(", ", map.iter().map(mapping(": "))))
+    }
+}
+
+/// Normalizes zeros and NaNs so that floats which should be
+/// interchangeable as map keys have the same bits
+fn float_bits(float: f64) -> u64 {
+    if float == 0.0 {
+        0.0f64.to_bits()
+    } else if float.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        float.to_bits()
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Record(a), Value::Record(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Set(a), Value::Set(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => float_bits(*a) == float_bits(*b),
+            (Value::String_(a), Value::String_(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Type(a), Value::Type(b)) => a == b,
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => a == b,
+            (Value::Optional(a), Value::Optional(b)) => a == b,
+            (Value::Cell(a), Value::Cell(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+
+        match self {
+            Value::Nil => (),
+            Value::Record(fields) => hash_unordered(fields.iter(), state),
+            Value::Tuple(values) => values.hash(state),
+            Value::List(values) => values.hash(state),
+            Value::Map(entries) => hash_unordered(entries.iter(), state),
+            Value::Set(elements) => hash_unordered(elements.iter(), state),
+            Value::Int(int) => int.hash(state),
+            Value::Float(float) => float_bits(*float).hash(state),
+            Value::String_(s) => s.hash(state),
+            Value::Bool(b) => b.hash(state),
+            // types can contain records, which can't be hashed in order,
+            // so all types go in the same bucket
+            Value::Type(_) => (),
+            Value::Builtin(builtin) => builtin.name.hash(state),
+            Value::Closure(closure) => (&*closure.body as *const Expr).hash(state),
+            Value::Optional(value) => value.hash(state),
+            Value::Cell(cell) => (&*cell.0 as *const Mutex<Value>).hash(state),
+        }
+    }
+}
+
+/// Hashes items in a way that doesn't depend on their order, for collections
+/// whose equality ignores the order
+fn hash_unordered<T: Hash, H: Hasher>(items: impl Iterator<Item=T>, state: &mut H) {
+    let combined = items.fold(0u64, |combined, item| {
+        let mut hasher = FnvHasher::default();
+        item.hash(&mut hasher);
+        combined.wrapping_add(hasher.finish())
+    });
+
+    combined.hash(state);
+}
+
+pub type BuiltinFn = dyn Fn(Vec<Value>) -> Result<Value, VmError> + Send + Sync;
+
+/// A function implemented in Rust. See `builtins::Builtins` for how to make one available.
+#[derive(Display, Clone)]
+#[display(fmt = "<builtin {}>", name)]
+pub struct Builtin {
+    pub name: Name,
+    pub function: Arc<BuiltinFn>,
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "Builtin({})", self.name)
+    }
+}
+
+impl Builtin {
+    /// A method of a trait, which takes the dictionary of an implementation
+    /// of the trait and returns the method's value in it
+    pub fn method(name: Name) -> Self {
+        let method = name.clone();
+        Builtin {
+            name,
+            function: Arc::new(move |args| match args.as_slice() {
+                [Value::Record(dictionary)] if dictionary.contains_key(&method) => Ok(dictionary[&method].clone()),
+                _ => type_error!("{} can't be called with ({})", method, join(", ", args.iter())),
+            }),
+        }
+    }
+}
+
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+/// A function defined in the language, along with the variables that were
+/// in scope where it was defined
+#[derive(Clone)]
+pub struct Closure {
+    pub params: Vec<Name>,
+    pub body: Arc<Expr>,
+    pub context: ValueContext,
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // a function that only takes the dictionaries of trait implementations
+        // is shown as the function it returns, where that's known
+        if self.params.iter().all(is_dictionary_name) {
+            match &self.body.kind {
+                ExprKind::Lambda(params, _) => {
+                    return write!(fmt, "<function |{}|>", join(", ", params.iter().map(|param| &param.name)));
+                }
+                ExprKind::Call(function, _) => if let ExprKind::Var(ident) = &function.kind {
+                    if let Some(value) = self.context.lookup(&ident.name) {
+                        return write!(fmt, "{}", value);
+                    }
+                },
+                _ => {}
+            }
+        }
+        write!(fmt, "<function |{}|>", join(", ", self.params.iter()))
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "Closure({})", self)
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.body, &other.body)
+    }
+}
+
+/// A mutable variable. Closures share it with the scope they're defined in,
+/// so they see the assignments made after they're created, and the ones they
+/// make are seen outside them.
+#[derive(Debug, Clone)]
+pub struct Cell(Arc<Mutex<Value>>);
+
+impl Cell {
+    pub fn new(value: Value) -> Self {
+        Cell(Arc::new(Mutex::new(value)))
+    }
+
+    pub fn get(&self) -> Value {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, value: Value) {
+        *self.0.lock().unwrap() = value;
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.get())
+    }
+}
+
+impl PartialEq for Cell {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
 impl Value {
+    /// Like `to_string`, but strings aren't quoted or escaped. This is how
+    /// values are shown by string interpolation and `print`.
+    pub fn to_unquoted_string(&self) -> String {
+        match self {
+            Value::String_(s) => s.clone(),
+            value => value.to_string(),
+        }
+    }
+
+    /// The value of a variable, which is in a cell if the variable is mutable
+    pub fn read(self) -> Value {
+        match self {
+            Value::Cell(cell) => cell.get(),
+            value => value,
+        }
+    }
+
+    /// Wraps the value in `some`, unless it is already optional
+    fn into_optional(self) -> Value {
+        match self {
+            value @ Value::Optional(_) => value,
+            value => Value::Optional(Some(Box::new(value))),
+        }
+    }
+
+    /// Whether the value is a `ty`, going by its shape, which is how `is` works.
+    /// Functions can't be looked inside, so they have every function type.
+    pub fn has_type(&self, ty: &Type) -> bool {
+        match (self, ty) {
+            (Value::Nil, Type::Nil)
+            | (Value::Int(_), Type::Int)
+            | (Value::Float(_), Type::Float)
+            | (Value::String_(_), Type::String_)
+            | (Value::Bool(_), Type::Bool)
+            | (Value::Optional(None), Type::Optional(_))
+            | (Value::Builtin(_), Type::Function(..))
+            | (Value::Closure(_), Type::Function(..)) => true,
+            (Value::String_(s), Type::Literal(literal)) => s == literal,
+            (Value::Type(inner), Type::Type(level)) => inner.level() <= *level,
+            (Value::Optional(Some(value)), Type::Optional(inner)) => value.has_type(inner),
+            (Value::List(values), Type::List(ty)) => values.iter().all(|value| value.has_type(ty)),
+            (Value::Set(values), Type::Set(ty)) => values.iter().all(|value| value.has_type(ty)),
+            (Value::Map(entries), Type::Map(key_type, value_type)) => {
+                entries.iter().all(|(key, value)| key.has_type(key_type) && value.has_type(value_type))
+            }
+            (Value::Tuple(values), Type::Tuple(types)) => {
+                let mut types = types.clone();
+                values.len() == types.len() && values.iter().enumerate().all(|(i, value)| {
+                    if !value.has_type(&types[i]) {
+                        return false;
+                    }
+                    substitute_later_fields(&mut types[i + 1..], &i.to_string().into(), value);
+                    true
+                })
+            }
+            // a record with more fields than an open record type still has that type
+            (Value::Record(fields), Type::Record(field_types, rest)) => {
+                let (names, mut types): (Vec<Name>, Vec<Type>) = field_types.clone().into_iter().unzip();
+                (rest.is_some() || fields.len() == names.len()) && names.iter().enumerate().all(|(i, name)| {
+                    match fields.get(name) {
+                        Some(value) if value.has_type(&types[i]) => {
+                            substitute_later_fields(&mut types[i + 1..], name, value);
+                            true
+                        }
+                        _ => false,
+                    }
+                })
+            }
+            (value, Type::Union(members)) => members.iter().any(|member| value.has_type(member)),
+            (value, ty @ Type::Named(..)) => value.has_type(&ty.unfold()),
+            (value, Type::Forall(_, inner)) => value.has_type(inner),
+            _ => false,
+        }
+    }
+
     fn access_record_field(&self, name: &Name) -> Result<Value, VmError> {
         match self {
             Value::Record(map) => {
@@ -63,8 +384,6 @@ impl Value {
     fn access_tuple_field(&self, number: usize) -> Result<Value, VmError> {
         match self {
             Value::Tuple(values) => {
-                let number: usize = number.into();
-
                 if let Some(value) = values.get(number) {
                     Ok(value.clone())
                 } else {
@@ -82,6 +401,44 @@ impl Value {
     }
 }
 
+pub(crate) fn evaluate_binop(op: BinOp, lhs: Value, rhs: Value) -> Result<Value, VmError> {
+    Ok(match (op, lhs, rhs) {
+        (BinOp::Add, Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs + rhs),
+        (BinOp::Sub, Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs - rhs),
+        (BinOp::Mul, Value::Int(lhs), Value::Int(rhs)) => Value::Int(lhs * rhs),
+        (BinOp::Div, Value::Int(lhs), Value::Int(rhs)) => {
+            if rhs.is_zero() {
+                type_error!("attempted to divide {} by zero", lhs)
+            }
+            Value::Int(lhs / rhs)
+        }
+        (BinOp::Add, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs + rhs),
+        (BinOp::Sub, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs - rhs),
+        (BinOp::Mul, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs * rhs),
+        (BinOp::Div, Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs / rhs),
+        (BinOp::Add, Value::String_(lhs), Value::String_(rhs))
+        | (BinOp::Concat, Value::String_(lhs), Value::String_(rhs)) => Value::String_(lhs + &rhs),
+        (op, lhs, rhs) => type_error!("cannot apply `{}` to {} and {}", op, lhs, rhs),
+    })
+}
+
+/// Calls a builtin or closure with arguments that have already been evaluated
+pub fn call(function: &Value, args: Vec<Value>) -> Result<Value, VmError> {
+    match function {
+        Value::Builtin(builtin) => (builtin.function)(args),
+        Value::Closure(closure) => {
+            if closure.params.len() != args.len() {
+                type_error!("{} expects {} arguments, found {}", closure, closure.params.len(), args.len())
+            }
+
+            let context = closure.params.iter().cloned().zip(args)
+                .fold(closure.context.clone(), |context, (param, arg)| context.extend(param, arg));
+            evaluate(&closure.body, &context)
+        }
+        _ => type_error!("expected a function, found {}", function),
+    }
+}
+
 pub fn evaluate_type(expr: &Expr, context: &ValueContext) -> Result<Type, VmError> {
     let value = evaluate(expr, context)?;
 
@@ -93,10 +450,43 @@ pub fn evaluate_type(expr: &Expr, context: &ValueContext) -> Result<Type, VmErro
 
 pub fn evaluate_stmt(stmt: &Stmt, context: &ValueContext) -> Result<ValueContext, VmError> {
     match &stmt.kind {
-        StmtKind::Let(ident, expr) => {
-            let value = evaluate(expr, &context)?;
+        StmtKind::Let(ident, _, expr) => {
+            let value = evaluate(expr, context)?;
             Ok(context.extend(ident.name.clone(), value))
         }
+        StmtKind::LetMut(ident, _, expr) => {
+            let value = evaluate(expr, context)?;
+            Ok(context.extend(ident.name.clone(), Value::Cell(Cell::new(value))))
+        }
+        StmtKind::Assign(ident, expr) => {
+            let value = evaluate(expr, context)?;
+            match context.lookup(&ident.name) {
+                Some(Value::Cell(cell)) => cell.set(value),
+                Some(_) => type_error!("can't assign to {}, which isn't mutable", ident.name),
+                None => type_error!("Unknown variable {}", ident.name),
+            }
+            Ok(context.clone())
+        }
+        StmtKind::Type(ident, ty_expr) => {
+            // inside its own definition, the name refers to the type being defined
+            let name = ident.name.clone();
+            let inner_context = context.extend(name.clone(), Value::Type(Type::Recursive(name.clone())));
+            let ty = evaluate_type(ty_expr, &inner_context)?;
+            Ok(context.extend(name.clone(), Value::Type(Type::Named(name, Box::new(ty)))))
+        }
+        StmtKind::Trait(_, methods) => {
+            Ok(methods.iter().fold(context.clone(), |context, (name, _, _)| {
+                context.extend(name.name.clone(), Value::Builtin(Builtin::method(name.name.clone())))
+            }))
+        }
+        StmtKind::Impl(trait_ident, ty_expr, methods) => {
+            let ty = evaluate_type(ty_expr, context)?;
+            let dictionary = methods.iter()
+                .map(|(name, expr)| Ok((name.name.clone(), evaluate(expr, context)?)))
+                .collect::<Result<_, VmError>>()?;
+            Ok(context.extend(dictionary_name(&trait_ident.name, &ty), Value::Record(dictionary)))
+        }
+        StmtKind::Import(..) => type_error!("modules can only be imported at the top level of a file"),
     }
 }
 
@@ -104,22 +494,53 @@ pub fn evaluate(expr: &Expr, context: &ValueContext) -> Result<Value, VmError> {
     Ok(match &expr.kind {
         ExprKind::Nil => Value::Nil,
         ExprKind::NilType => Value::Type(Type::Nil),
+        ExprKind::Universe(level) => Value::Type(Type::Type(*level)),
 
         ExprKind::RecordValue(entries) => {
-            let map = entries.iter().try_fold(Map::default(), |mut map, (ident, expr)| {
-                map.insert(ident.name.clone(), evaluate(expr, context)?);
+            let map = entries.iter().try_fold(OrderedMap::default(), |mut map, (ident, expr)| {
+                if map.insert(ident.name.clone(), evaluate(expr, context)?).is_some() {
+                    type_error!("duplicate record field {}", ident.name);
+                }
                 Ok(map)
             })?;
 
             Value::Record(map)
         }
+        ExprKind::RecordExtension(record, entries) => {
+            match evaluate(record, context)? {
+                Value::Record(mut map) => {
+                    for (ident, expr) in entries {
+                        map.insert(ident.name.clone(), evaluate(expr, context)?);
+                    }
+                    Value::Record(map)
+                }
+                value => type_error!("expected a record, found {}", value),
+            }
+        }
+        ExprKind::RecordRemoval(record, fields) => {
+            match evaluate(record, context)? {
+                Value::Record(map) => {
+                    let map = map.into_iter()
+                        .filter(|(name, _)| !fields.iter().any(|ident| ident.name == *name))
+                        .collect();
+                    Value::Record(map)
+                }
+                value => type_error!("expected a record, found {}", value),
+            }
+        }
         ExprKind::RecordType(entries) => {
-            let map = entries.iter().try_fold(Map::default(), |mut map, (ident, expr)| {
-                map.insert(ident.name.clone(), evaluate_type(expr, context)?);
-                Ok(map)
-            })?;
+            // each field is in scope in the types of the fields after it
+            let mut context = context.clone();
+            let mut map = OrderedMap::default();
+
+            for (ident, expr) in entries {
+                if map.insert(ident.name.clone(), evaluate_type(expr, &context)?).is_some() {
+                    type_error!("duplicate record type field {}", ident.name);
+                }
+                context = context.extend(ident.name.clone(), Value::Type(Type::Field(ident.name.clone())));
+            }
 
-            Value::Type(Type::Record(map))
+            Value::Type(Type::Record(map, None))
         }
         ExprKind::Tuple(exprs) => {
             let values = exprs.iter().try_fold(Vec::new(), |mut values, expr| {
@@ -129,14 +550,63 @@ pub fn evaluate(expr: &Expr, context: &ValueContext) -> Result<Value, VmError> {
 
             Value::Tuple(values)
         }
-        ExprKind::TupleType(exprs) => {
-            let values = exprs.iter().try_fold(Vec::new(), |mut values, expr| {
-                values.push(evaluate_type(expr, context)?);
-                Ok(values)
-            })?;
+        ExprKind::TupleType(elements) => {
+            // named elements are in scope in the types after them
+            let mut context = context.clone();
+            let mut values = Vec::new();
+
+            for (number, (ident, expr)) in elements.iter().enumerate() {
+                values.push(evaluate_type(expr, &context)?);
+                if let Some(ident) = ident {
+                    let field = Type::Field(number.to_string().into());
+                    context = context.extend(ident.name.clone(), Value::Type(field));
+                }
+            }
 
             Value::Type(Type::Tuple(values))
         }
+        ExprKind::List(exprs) => {
+            let values = exprs.iter()
+                .map(|expr| evaluate(expr, context))
+                .collect::<Result<_, _>>()?;
+
+            Value::List(values)
+        }
+        ExprKind::ListType(expr) => Value::Type(Type::List(Box::new(evaluate_type(expr, context)?))),
+        ExprKind::FunctionType(params, ret) => {
+            let params = params.iter()
+                .map(|param| evaluate_type(param, context))
+                .collect::<Result<_, _>>()?;
+
+            Value::Type(Type::Function(params, Box::new(evaluate_type(ret, context)?)))
+        }
+        ExprKind::Map(entries) => {
+            let map = entries.iter().try_fold(OrderedMap::default(), |mut map, (key, value)| {
+                let key = evaluate(key, context)?;
+                let value = evaluate(value, context)?;
+                if map.contains_key(&key) {
+                    type_error!("duplicate map key {}", key);
+                }
+                map.insert(key, value);
+                Ok(map)
+            })?;
+
+            Value::Map(map)
+        }
+        ExprKind::MapType(key, value) => {
+            let key = evaluate_type(key, context)?;
+            let value = evaluate_type(value, context)?;
+
+            Value::Type(Type::Map(Box::new(key), Box::new(value)))
+        }
+        ExprKind::Set(exprs) => {
+            let set = exprs.iter()
+                .map(|expr| evaluate(expr, context))
+                .collect::<Result<_, _>>()?;
+
+            Value::Set(set)
+        }
+        ExprKind::SetType(expr) => Value::Type(Type::Set(Box::new(evaluate_type(expr, context)?))),
         ExprKind::Block(stmts, expr) => {
             let context = stmts.iter()
                 .try_fold(context.clone(), |context, stmt| evaluate_stmt(stmt, &context))?;
@@ -148,7 +618,7 @@ pub fn evaluate(expr: &Expr, context: &ValueContext) -> Result<Value, VmError> {
         }
         ExprKind::Var(ident) => {
             match context.lookup(&ident.name) {
-                Some(value) => value.clone(),
+                Some(value) => value.clone().read(),
                 None => type_error!("Unknown variable {}", ident.name),
             }
         },
@@ -158,8 +628,110 @@ pub fn evaluate(expr: &Expr, context: &ValueContext) -> Result<Value, VmError> {
         ExprKind::TupleFieldAccess(ref expr, ref field_number) => {
             evaluate(expr, context)?.access_tuple_field(*field_number)?
         }
-        ExprKind::NumberLiteral(number) => Value::Number(*number),
+        ExprKind::OptionalRecordFieldAccess(ref expr, ref field_name) => {
+            match evaluate(expr, context)? {
+                Value::Optional(Some(value)) => value.access_record_field(&field_name.name)?.into_optional(),
+                Value::Optional(None) => Value::Optional(None),
+                value => type_error!("expected optional record with field `{}`, found {}", field_name, value),
+            }
+        }
+        ExprKind::OptionalTupleFieldAccess(ref expr, ref field_number) => {
+            match evaluate(expr, context)? {
+                Value::Optional(Some(value)) => value.access_tuple_field(*field_number)?.into_optional(),
+                Value::Optional(None) => Value::Optional(None),
+                value => type_error!("expected optional tuple, found {}", value),
+            }
+        }
+        ExprKind::OptionalType(ref expr) => {
+            Value::Type(Type::Optional(Box::new(evaluate_type(expr, context)?)))
+        }
+        ExprKind::UnionType(ref lhs, ref rhs) => {
+            Value::Type(Type::union(vec![evaluate_type(lhs, context)?, evaluate_type(rhs, context)?]))
+        }
+        ExprKind::Is(ref value, ref ty) => {
+            let value = evaluate(value, context)?;
+            Value::Bool(value.has_type(&evaluate_type(ty, context)?))
+        }
+        ExprKind::IntLiteral(int) => Value::Int(int.clone()),
+        ExprKind::FloatLiteral(float) => Value::Float(*float),
         ExprKind::StringLiteral(s) => Value::String_(s.clone()),
+        ExprKind::Interpolated(parts) => {
+            let mut output = String::new();
+
+            for part in parts {
+                match part {
+                    StringPart::Literal(s) => output.push_str(s),
+                    StringPart::Expr(expr) => {
+                        output.push_str(&evaluate(expr, context)?.to_unquoted_string())
+                    }
+                }
+            }
+
+            Value::String_(output)
+        }
         ExprKind::Parenthesized(ref expr) => evaluate(expr, context)?,
+        ExprKind::Negate(ref expr) => {
+            match evaluate(expr, context)? {
+                Value::Int(int) => Value::Int(-int),
+                Value::Float(float) => Value::Float(-float),
+                value => type_error!("cannot negate {}", value),
+            }
+        }
+        ExprKind::BinOp(op, ref lhs, ref rhs) => {
+            evaluate_binop(*op, evaluate(lhs, context)?, evaluate(rhs, context)?)?
+        }
+        ExprKind::Call(ref function, ref args) => {
+            let function = evaluate(function, context)?;
+            let args = args.iter()
+                .map(|arg| evaluate(arg, context))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            call(&function, args)?
+        }
+        // values carry their shape with them, so they don't need wrapping to be used
+        // as a union, but one that doesn't have the type it's coerced to is a bug
+        ExprKind::Coerce(expr, ty) => {
+            let value = evaluate(expr, context)?;
+            if !value.has_type(ty) {
+                type_error!("expected a value of type {}, found {}", ty, value);
+            }
+            value
+        }
+        ExprKind::Lambda(params, body) => {
+            Value::Closure(Closure {
+                params: params.iter().map(|param| param.name.clone()).collect(),
+                body: Arc::new((**body).clone()),
+                context: context.clone(),
+            })
+        }
+        ExprKind::Coalesce(ref lhs, ref rhs) => {
+            match evaluate(lhs, context)? {
+                Value::Optional(Some(value)) => *value,
+                Value::Optional(None) => evaluate(rhs, context)?,
+                value => type_error!("expected optional, found {}", value),
+            }
+        }
+        ExprKind::If(ref condition, ref then, ref otherwise) => {
+            match evaluate(condition, context)? {
+                Value::Bool(true) => evaluate(then, context)?,
+                Value::Bool(false) => match otherwise {
+                    Some(otherwise) => evaluate(otherwise, context)?,
+                    None => Value::Nil,
+                },
+                value => type_error!("expected a Bool condition, found {}", value),
+            }
+        }
+        ExprKind::IfLet(ref ident, ref value, ref then, ref otherwise) => {
+            match evaluate(value, context)? {
+                Value::Optional(Some(value)) => {
+                    evaluate(then, &context.extend(ident.name.clone(), *value))?
+                }
+                Value::Optional(None) => match otherwise {
+                    Some(otherwise) => evaluate(otherwise, context)?,
+                    None => Value::Nil,
+                },
+                value => type_error!("expected optional, found {}", value),
+            }
+        }
     })
 }